@@ -0,0 +1,142 @@
+use crate::{OpenActionError, OpenActionResult as Result};
+
+/// Command-line arguments passed to the plugin by the OpenAction server
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PluginArgs {
+	/// Port of the OpenAction server's WebSocket
+	pub port: u16,
+	/// UUID of the plugin
+	pub plugin_uuid: String,
+	/// Name of the event used to register the plugin
+	pub register_event: String,
+	/// JSON-encoded information about the application, plugin and connected devices
+	pub info: String,
+}
+
+impl PluginArgs {
+	/// Parse plugin arguments in either `-flag value` or `-flag=value` form, ignoring the case of flags
+	pub fn parse(args: impl IntoIterator<Item = impl Into<String>>) -> Result<Self> {
+		let mut port = None;
+		let mut plugin_uuid = None;
+		let mut register_event = None;
+		let mut info = None;
+
+		let mut args = args.into_iter().map(Into::into);
+		while let Some(arg) = args.next() {
+			let (flag, value) = match arg.split_once('=') {
+				Some((flag, value)) => (flag.trim().to_lowercase(), Some(value.to_owned())),
+				None => (arg.trim().to_lowercase(), None),
+			};
+			let slot = match flag.as_str() {
+				"-port" => &mut port,
+				"-pluginuuid" => &mut plugin_uuid,
+				"-registerevent" => &mut register_event,
+				"-info" => &mut info,
+				_ => continue,
+			};
+			let Some(value) = value.or_else(|| args.next()) else {
+				return Err(OpenActionError::InvalidArgs(format!(
+					"missing value for CLI flag: {}",
+					flag
+				)));
+			};
+			*slot = Some(value);
+		}
+
+		let require = |value: Option<String>, flag: &str| {
+			value.ok_or_else(|| OpenActionError::InvalidArgs(format!("missing CLI flag: {}", flag)))
+		};
+		let port = require(port, "-port")?;
+		Ok(Self {
+			port: port
+				.trim()
+				.parse()
+				.map_err(|_| OpenActionError::InvalidArgs(format!("invalid port: {}", port)))?,
+			plugin_uuid: require(plugin_uuid, "-pluginUUID")?,
+			register_event: require(register_event, "-registerEvent")?,
+			info: require(info, "-info")?,
+		})
+	}
+
+	/// Parse plugin arguments from the arguments this process was started with
+	pub fn from_env() -> Result<Self> {
+		Self::parse(std::env::args())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn expected() -> PluginArgs {
+		PluginArgs {
+			port: 57116,
+			plugin_uuid: "com.example.plugin".to_owned(),
+			register_event: "registerPlugin".to_owned(),
+			info: r#"{"a":"b=c"}"#.to_owned(),
+		}
+	}
+
+	#[test]
+	fn parses_separate_values() {
+		let args = PluginArgs::parse([
+			"plugin",
+			"-port",
+			"57116",
+			"-pluginUUID",
+			"com.example.plugin",
+			"-registerEvent",
+			"registerPlugin",
+			"-info",
+			r#"{"a":"b=c"}"#,
+		]);
+		assert_eq!(args.unwrap(), expected());
+	}
+
+	#[test]
+	fn parses_inline_values() {
+		let args = PluginArgs::parse([
+			"-port=57116",
+			"-pluginUUID=com.example.plugin",
+			"-registerEvent=registerPlugin",
+			r#"-info={"a":"b=c"}"#,
+		]);
+		assert_eq!(args.unwrap(), expected());
+	}
+
+	#[test]
+	fn ignores_case_of_flags() {
+		let args = PluginArgs::parse([
+			"-PORT",
+			"57116",
+			"-pluginuuid=com.example.plugin",
+			"-RegisterEvent",
+			"registerPlugin",
+			r#"-INFO={"a":"b=c"}"#,
+		]);
+		assert_eq!(args.unwrap(), expected());
+	}
+
+	#[test]
+	fn rejects_missing_flag() {
+		let args = PluginArgs::parse(["-port", "57116", "-pluginUUID", "com.example.plugin", "-info", "{}"]);
+		assert!(matches!(args, Err(OpenActionError::InvalidArgs(message)) if message.contains("-registerEvent")));
+	}
+
+	#[test]
+	fn rejects_missing_value() {
+		let args = PluginArgs::parse(["-pluginUUID", "com.example.plugin", "-port"]);
+		assert!(matches!(args, Err(OpenActionError::InvalidArgs(message)) if message.contains("missing value")));
+	}
+
+	#[test]
+	fn rejects_invalid_port() {
+		let args = PluginArgs::parse([
+			"-port=70000",
+			"-pluginUUID=com.example.plugin",
+			"-registerEvent=registerPlugin",
+			"-info={}",
+		]);
+		assert!(matches!(args, Err(OpenActionError::InvalidArgs(message)) if message.contains("invalid port")));
+	}
+}
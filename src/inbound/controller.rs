@@ -0,0 +1,48 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// The type of control an action instance is bound to
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum Controller {
+	Keypad,
+	Encoder,
+	/// A controller not known to this version of the crate
+	Unknown(String),
+}
+
+impl Controller {
+	pub fn as_str(&self) -> &str {
+		match self {
+			Self::Keypad => "Keypad",
+			Self::Encoder => "Encoder",
+			Self::Unknown(controller) => controller,
+		}
+	}
+}
+
+impl From<String> for Controller {
+	fn from(controller: String) -> Self {
+		match controller.as_str() {
+			"Keypad" => Self::Keypad,
+			"Encoder" => Self::Encoder,
+			_ => Self::Unknown(controller),
+		}
+	}
+}
+
+impl From<Controller> for String {
+	fn from(controller: Controller) -> Self {
+		match controller {
+			Controller::Unknown(controller) => controller,
+			controller => controller.as_str().to_owned(),
+		}
+	}
+}
+
+impl fmt::Display for Controller {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(self.as_str())
+	}
+}
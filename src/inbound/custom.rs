@@ -0,0 +1,31 @@
+use crate::OpenActionResult as Result;
+
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+
+#[async_trait]
+/// Event handler trait for inbound events not defined by the OpenAction protocol, such as server extensions
+pub trait CustomEventHandler: Send + Sync + 'static {
+	/// Value of the `event` field of the events handled by this handler
+	const EVENT: &'static str;
+
+	/// Type the whole event message is deserialized into
+	type Event: DeserializeOwned + Send + 'static;
+
+	async fn handle(&self, event: Self::Event) -> Result<()>;
+}
+
+#[async_trait]
+pub(crate) trait ErasedCustomEventHandler: Send + Sync {
+	async fn call(&self, event: serde_json::Value) -> Result<()>;
+}
+
+pub(crate) struct CustomEventWrapper<H: CustomEventHandler>(pub(crate) H);
+
+#[async_trait]
+impl<H: CustomEventHandler> ErasedCustomEventHandler for CustomEventWrapper<H> {
+	async fn call(&self, event: serde_json::Value) -> Result<()> {
+		let event = serde_json::from_value(event)?;
+		self.0.handle(event).await
+	}
+}
@@ -1,3 +1,5 @@
+use super::Controller;
+
 use serde::Deserialize;
 
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
@@ -6,12 +8,120 @@ pub struct DeviceSizeInfo {
 	pub columns: u8,
 }
 
+/// The model of a device, as numbered by the Stream Deck SDK
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash)]
+#[serde(from = "u8")]
+pub enum DeviceType {
+	StreamDeck,
+	StreamDeckMini,
+	StreamDeckXL,
+	StreamDeckMobile,
+	CorsairGKeys,
+	StreamDeckPedal,
+	CorsairVoyager,
+	StreamDeckPlus,
+	ScufController,
+	StreamDeckNeo,
+	StreamDeckStudio,
+	VirtualStreamDeck,
+	/// A device type not known to this version of the crate
+	Unknown(u8),
+}
+
+impl From<u8> for DeviceType {
+	fn from(value: u8) -> Self {
+		match value {
+			0 => Self::StreamDeck,
+			1 => Self::StreamDeckMini,
+			2 => Self::StreamDeckXL,
+			3 => Self::StreamDeckMobile,
+			4 => Self::CorsairGKeys,
+			5 => Self::StreamDeckPedal,
+			6 => Self::CorsairVoyager,
+			7 => Self::StreamDeckPlus,
+			8 => Self::ScufController,
+			9 => Self::StreamDeckNeo,
+			10 => Self::StreamDeckStudio,
+			11 => Self::VirtualStreamDeck,
+			value => Self::Unknown(value),
+		}
+	}
+}
+
+impl DeviceType {
+	/// Number of encoders (dials) on this model, according to the Stream Deck SDK
+	pub fn encoder_count(self) -> u8 {
+		match self {
+			Self::StreamDeckPlus => 4,
+			Self::StreamDeckStudio => 2,
+			_ => 0,
+		}
+	}
+
+	/// Whether this model has a touch strip above its encoders
+	pub fn has_touch_strip(self) -> bool {
+		matches!(self, Self::StreamDeckPlus)
+	}
+
+	/// Whether the keys of this model can display images
+	pub fn has_key_displays(self) -> bool {
+		!matches!(self, Self::StreamDeckPedal | Self::CorsairGKeys | Self::ScufController)
+	}
+
+	/// Native width and height in pixels of the images shown on the keys of this model, or `None` if its keys cannot
+	/// display images
+	///
+	/// Models without a known size, including devices added by device plugins, use the standard 72 by 72 pixels.
+	pub fn key_image_size(self) -> Option<(u16, u16)> {
+		if !self.has_key_displays() {
+			return None;
+		}
+		Some(match self {
+			Self::StreamDeckMini => (80, 80),
+			Self::StreamDeckXL | Self::StreamDeckNeo => (96, 96),
+			Self::StreamDeckPlus => (120, 120),
+			Self::StreamDeckStudio => (144, 112),
+			_ => (72, 72),
+		})
+	}
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct DeviceInfo {
 	pub id: String,
 	pub name: String,
 	pub size: DeviceSizeInfo,
-	pub r#type: u8,
+	/// Number of encoders (dials) on this device, if reported by the OpenAction server
+	#[serde(default)]
+	pub encoders: Option<u8>,
+	pub r#type: DeviceType,
+}
+
+impl DeviceInfo {
+	/// Number of keys on this device
+	pub fn key_count(&self) -> u16 {
+		u16::from(self.size.rows) * u16::from(self.size.columns)
+	}
+
+	/// Number of encoders (dials) on this device
+	///
+	/// Falls back to [`DeviceType::encoder_count`] if the OpenAction server did not report it.
+	pub fn encoder_count(&self) -> u8 {
+		self.encoders.unwrap_or_else(|| self.r#type.encoder_count())
+	}
+
+	/// Whether this device has a touch strip above its encoders
+	pub fn has_touch_strip(&self) -> bool {
+		self.r#type.has_touch_strip()
+	}
+
+	/// Native width and height in pixels of key images for this device, or `None` if its keys cannot display images
+	///
+	/// See [`DeviceType::key_image_size`]. The size does not depend on the device pixel ratio of the display the
+	/// OpenAction server runs on.
+	pub fn key_pixel_size(&self) -> Option<(u16, u16)> {
+		self.r#type.key_image_size()
+	}
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -29,7 +139,7 @@ pub struct DeviceDidDisconnectEvent {
 #[derive(Clone, Debug, Deserialize)]
 pub struct SetImageEvent {
 	pub device: String,
-	pub controller: Option<String>,
+	pub controller: Option<Controller>,
 	pub position: Option<u8>,
 	pub image: Option<String>,
 }
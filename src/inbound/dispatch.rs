@@ -0,0 +1,89 @@
+use super::InboundEventType;
+
+use crate::runtime::Runtime;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// How inbound events are dispatched to event handlers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DispatchMode {
+	/// Handle each event to completion before reading the next one
+	#[default]
+	Sequential,
+	/// Handle events for different instances concurrently on spawned tasks, while events for the same instance, and
+	/// global events, are still handled in the order they were received
+	Concurrent,
+}
+
+struct Lane {
+	sender: mpsc::UnboundedSender<InboundEventType>,
+	task: JoinHandle<()>,
+}
+
+impl Lane {
+	fn spawn(runtime: Arc<Runtime>, previous: Option<JoinHandle<()>>) -> Self {
+		let (sender, mut receiver) = mpsc::unbounded_channel();
+		let task = tokio::spawn(async move {
+			// Finish handling events for a previous instance with the same context first
+			if let Some(previous) = previous {
+				let _ = previous.await;
+			}
+			while let Some(event) = receiver.recv().await {
+				super::dispatch(&runtime, event).await;
+			}
+		});
+		Self { sender, task }
+	}
+}
+
+/// Per-instance queues of events, each drained in order by its own task, plus one queue for global events
+pub(super) struct Lanes {
+	runtime: Arc<Runtime>,
+	lanes: HashMap<Option<String>, Lane>,
+	retired: HashMap<String, JoinHandle<()>>,
+}
+
+impl Lanes {
+	pub(super) fn new(runtime: Arc<Runtime>) -> Self {
+		Self {
+			runtime,
+			lanes: HashMap::new(),
+			retired: HashMap::new(),
+		}
+	}
+
+	pub(super) fn dispatch(&mut self, event: InboundEventType) {
+		let key = event.context().map(str::to_owned);
+		let disappearing = matches!(event, InboundEventType::WillDisappear(_));
+
+		let lane = self.lanes.entry(key.clone()).or_insert_with(|| {
+			let previous = key.as_ref().and_then(|context| self.retired.remove(context));
+			Lane::spawn(self.runtime.clone(), previous)
+		});
+		let _ = lane.sender.send(event);
+
+		if disappearing
+			&& let Some(context) = key
+			&& let Some(lane) = self.lanes.remove(&Some(context.clone()))
+		{
+			self.retired.retain(|_, task| !task.is_finished());
+			self.retired.insert(context, lane.task);
+		}
+	}
+
+	/// Wait for all queued events to be handled
+	pub(super) async fn finish(self) {
+		let tasks = self
+			.lanes
+			.into_values()
+			.map(|lane| lane.task)
+			.chain(self.retired.into_values());
+		for task in tasks {
+			let _ = task.await;
+		}
+	}
+}
@@ -1,4 +1,4 @@
-use super::{Coordinates, SettingsValue};
+use super::{Controller, Coordinates, SettingsValue};
 
 use serde::Deserialize;
 
@@ -9,6 +9,8 @@ pub struct DialRotatePayload {
 	pub coordinates: Coordinates,
 	pub ticks: i16,
 	pub pressed: bool,
+	#[serde(default, rename = "isInMultiAction")]
+	pub is_in_multi_action: bool,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -23,10 +25,12 @@ pub struct DialRotateEvent {
 #[derive(Clone, Debug, Deserialize)]
 pub struct DialPressPayload {
 	#[allow(dead_code)]
-	pub controller: String,
+	pub controller: Controller,
 	pub settings: SettingsValue,
 	#[allow(dead_code)]
 	pub coordinates: Coordinates,
+	#[serde(default, rename = "isInMultiAction")]
+	pub is_in_multi_action: bool,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -42,12 +46,14 @@ pub struct DialPressEvent {
 #[allow(non_snake_case)]
 pub struct TouchTapPayload {
 	#[allow(dead_code)]
-	pub controller: String,
+	pub controller: Controller,
 	pub settings: SettingsValue,
 	#[allow(dead_code)]
 	pub coordinates: Coordinates,
 	pub tapPos: (u16, u16),
 	pub hold: bool,
+	#[serde(default, rename = "isInMultiAction")]
+	pub is_in_multi_action: bool,
 }
 
 #[derive(Clone, Debug, Deserialize)]
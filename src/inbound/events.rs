@@ -0,0 +1,59 @@
+use super::*;
+
+use crate::Instance;
+
+use std::sync::Arc;
+
+/// An inbound event, with the instance it relates to resolved for instance events
+///
+/// Received from [`events`](crate::events::events) alongside any registered [`Action`](crate::Action)s and
+/// [`GlobalEventHandler`]s.
+#[derive(Clone)]
+pub enum Event {
+	/* Global events */
+	SetImage(SetImageEvent),
+	SetBrightness(SetBrightnessEvent),
+	DidReceiveGlobalSettings(DidReceiveGlobalSettingsEvent),
+	DeviceDidConnect(DeviceDidConnectEvent),
+	DeviceDidDisconnect(DeviceDidDisconnectEvent),
+	ApplicationDidLaunch(ApplicationEvent),
+	ApplicationDidTerminate(ApplicationEvent),
+	DidReceiveDeepLink(DidReceiveDeepLinkEvent),
+	SystemDidWakeUp(SystemDidWakeUpEvent),
+	/* Instance events */
+	KeyDown(Arc<Instance>, KeyEvent),
+	KeyUp(Arc<Instance>, KeyEvent),
+	DialDown(Arc<Instance>, DialPressEvent),
+	DialUp(Arc<Instance>, DialPressEvent),
+	DialRotate(Arc<Instance>, DialRotateEvent),
+	TouchTap(Arc<Instance>, TouchTapEvent),
+	DidReceiveSettings(Arc<Instance>, DidReceiveSettingsEvent),
+	WillAppear(Arc<Instance>, AppearEvent),
+	WillDisappear(Arc<Instance>, AppearEvent),
+	TitleParametersDidChange(Arc<Instance>, TitleParametersDidChangeEvent),
+	PropertyInspectorDidAppear(Arc<Instance>, PropertyInspectorAppearEvent),
+	PropertyInspectorDidDisappear(Arc<Instance>, PropertyInspectorAppearEvent),
+	SendToPlugin(Arc<Instance>, SendToPluginEvent),
+	/* Events not defined by the OpenAction protocol */
+	Unknown(serde_json::Value),
+}
+
+impl Event {
+	/// The instance this event relates to, or `None` for global events
+	pub fn instance(&self) -> Option<&Arc<Instance>> {
+		match self {
+			Self::KeyDown(instance, _) | Self::KeyUp(instance, _) => Some(instance),
+			Self::DialDown(instance, _) | Self::DialUp(instance, _) => Some(instance),
+			Self::DialRotate(instance, _) => Some(instance),
+			Self::TouchTap(instance, _) => Some(instance),
+			Self::DidReceiveSettings(instance, _) => Some(instance),
+			Self::WillAppear(instance, _) | Self::WillDisappear(instance, _) => Some(instance),
+			Self::TitleParametersDidChange(instance, _) => Some(instance),
+			Self::PropertyInspectorDidAppear(instance, _) | Self::PropertyInspectorDidDisappear(instance, _) => {
+				Some(instance)
+			}
+			Self::SendToPlugin(instance, _) => Some(instance),
+			_ => None,
+		}
+	}
+}
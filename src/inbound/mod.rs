@@ -1,29 +1,43 @@
 mod applications;
+mod controller;
+mod custom;
 mod deep_link;
 mod devices;
+mod dispatch;
 mod encoder;
+mod events;
 mod keypad;
 mod misc;
 mod property_inspector;
-pub(crate) mod registration;
+mod registration;
 mod settings;
 mod states;
+mod subscribers;
 mod will_appear;
 
 pub use applications::*;
+pub use controller::Controller;
+pub use custom::CustomEventHandler;
+pub(crate) use custom::{CustomEventWrapper, ErasedCustomEventHandler};
 pub use deep_link::*;
 pub use devices::*;
+pub use dispatch::DispatchMode;
 pub use encoder::*;
+pub use events::Event;
 pub use keypad::*;
 pub use misc::*;
 pub use property_inspector::*;
+pub use registration::*;
 pub use settings::*;
 pub use states::*;
+pub use subscribers::GlobalEventSubscription;
+pub(crate) use subscribers::Subscribers;
 pub use will_appear::*;
 
-use crate::{OpenActionResult as Result, runtime::inbound as runtime};
+use crate::OpenActionResult as Result;
+use crate::runtime::{Runtime, catch_panic, with_timeout};
 
-use std::sync::OnceLock;
+use std::sync::Arc;
 
 use async_trait::async_trait;
 use futures_util::{StreamExt, stream::SplitStream};
@@ -32,6 +46,8 @@ use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, tungstenite::Message};
 
 type SettingsValue = serde_json::Value;
 
+pub(crate) type Stream = SplitStream<WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>>;
+
 /// The coordinates of an action instance on the device surface
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
 pub struct Coordinates {
@@ -39,14 +55,18 @@ pub struct Coordinates {
 	pub column: u8,
 }
 
+/// The payload shared by most instance events
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct GenericInstancePayload {
+pub struct GenericInstancePayload {
 	pub settings: SettingsValue,
 	pub coordinates: Option<Coordinates>,
-	pub controller: String,
+	pub controller: Controller,
 	#[serde(default)]
 	pub state: u16,
+	/// The state the user selected for this instance within a Multi Action, only sent with key events
+	pub user_desired_state: Option<u16>,
+	#[serde(default)]
 	pub is_in_multi_action: bool,
 }
 
@@ -78,8 +98,37 @@ enum InboundEventType {
 	PropertyInspectorDidAppear(PropertyInspectorAppearEvent),
 	PropertyInspectorDidDisappear(PropertyInspectorAppearEvent),
 	SendToPlugin(SendToPluginEvent),
+	/* Events not defined above, handled by custom event handlers */
+	#[serde(skip)]
+	Unknown(serde_json::Value),
 }
 
+/// Values of the `event` field of the variants of [`InboundEventType`], other than `Unknown`
+const KNOWN_EVENTS: &[&str] = &[
+	"setImage",
+	"setBrightness",
+	"didReceiveGlobalSettings",
+	"deviceDidConnect",
+	"deviceDidDisconnect",
+	"applicationDidLaunch",
+	"applicationDidTerminate",
+	"didReceiveDeepLink",
+	"systemDidWakeUp",
+	"keyDown",
+	"keyUp",
+	"dialDown",
+	"dialUp",
+	"dialRotate",
+	"touchTap",
+	"didReceiveSettings",
+	"willAppear",
+	"willDisappear",
+	"titleParametersDidChange",
+	"propertyInspectorDidAppear",
+	"propertyInspectorDidDisappear",
+	"sendToPlugin",
+];
+
 #[async_trait]
 /// Event handler trait for events that do not relate to a specific instance of an action
 pub trait GlobalEventHandler: Send + Sync {
@@ -87,6 +136,20 @@ pub trait GlobalEventHandler: Send + Sync {
 		Ok(())
 	}
 
+	/// Called when the connection to the OpenAction server is lost, before attempting to reconnect
+	///
+	/// By then, every instance has been passed to [`Action::will_disappear`](crate::Action::will_disappear) and
+	/// connected devices have been forgotten. The server sends `willAppear` and `deviceDidConnect` again for those that
+	/// still exist once the plugin has reconnected.
+	async fn plugin_disconnected(&self) -> Result<()> {
+		Ok(())
+	}
+
+	/// Called when the plugin has reconnected and re-registered with the OpenAction server
+	async fn plugin_reconnected(&self) -> Result<()> {
+		Ok(())
+	}
+
 	async fn device_plugin_set_image(&self, _event: SetImageEvent) -> Result<()> {
 		Ok(())
 	}
@@ -122,127 +185,237 @@ pub trait GlobalEventHandler: Send + Sync {
 	async fn system_did_wake_up(&self, _event: SystemDidWakeUpEvent) -> Result<()> {
 		Ok(())
 	}
-}
 
-static GLOBAL_EVENT_HANDLER: OnceLock<&'static dyn GlobalEventHandler> = OnceLock::new();
+	/// Called with every inbound event that is not defined by the OpenAction protocol, including events handled by a
+	/// [`CustomEventHandler`]
+	async fn raw_event(&self, _event: &serde_json::Value) -> Result<()> {
+		Ok(())
+	}
+}
 
-/// Register the handler for global events (does nothing if already set)
+/// Register the handler for global events of the default plugin (does nothing if already set)
+///
+/// Use [`subscribe_global_events`] to register more than one handler.
 pub fn set_global_event_handler(handler: &'static dyn GlobalEventHandler) {
-	let _ = GLOBAL_EVENT_HANDLER.set(handler);
+	crate::runtime::default_plugin().set_global_event_handler(handler);
+}
+
+/// Subscribe a handler to global events of the default plugin until the returned subscription is dropped
+///
+/// Handlers are called in the order they were subscribed.
+pub fn subscribe_global_events(handler: Arc<dyn GlobalEventHandler>) -> GlobalEventSubscription {
+	crate::runtime::default_plugin().subscribe_global_events(handler)
 }
 
-pub(crate) async fn process_incoming_messages(
-	mut stream: SplitStream<WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>>,
-) {
-	if let Some(handler) = GLOBAL_EVENT_HANDLER.get()
-		&& let Err(error) = handler.plugin_ready().await
-	{
-		log::error!("Failed to run plugin ready handler: {}", error);
+/// Call a method of every global event handler with a clone of the event
+macro_rules! notify {
+	($runtime:expr, $name:literal, $method:ident, $event:expr) => {{
+		let timeout = *$runtime.handler_timeout.lock().await;
+		$runtime
+			.global_event_handlers
+			.each($name, timeout, |handler| {
+				let event = $event.clone();
+				async move { handler.$method(event).await }
+			})
+			.await;
+		Ok(())
+	}};
+}
+
+pub(crate) async fn handle_disconnect(runtime: &Runtime) {
+	let (handlers, timeout) = (&runtime.global_event_handlers, *runtime.handler_timeout.lock().await);
+	handlers
+		.each("pluginDisconnected", timeout, |handler| async move {
+			handler.plugin_disconnected().await
+		})
+		.await;
+}
+
+/// Dispatch inbound events until the connection is lost, returning `true` if a shutdown was requested instead
+pub(crate) async fn process_incoming_messages(runtime: &Arc<Runtime>, mut stream: Stream, reconnected: bool) -> bool {
+	let (handlers, timeout) = (&runtime.global_event_handlers, *runtime.handler_timeout.lock().await);
+	if reconnected {
+		handlers
+			.each("pluginReconnected", timeout, |handler| async move {
+				handler.plugin_reconnected().await
+			})
+			.await;
+	} else {
+		handlers
+			.each(
+				"pluginReady",
+				timeout,
+				|handler| async move { handler.plugin_ready().await },
+			)
+			.await;
 	}
 
-	while let Some(message) = stream.next().await {
-		let Ok(data) = message else {
-			continue;
+	let mode = *runtime.dispatch_mode.lock().await;
+	let mut lanes = match mode {
+		DispatchMode::Sequential => None,
+		DispatchMode::Concurrent => Some(dispatch::Lanes::new(runtime.clone())),
+	};
+
+	let mut shutdown = false;
+	loop {
+		let message = tokio::select! {
+			message = stream.next() => message,
+			() = runtime.shutdown_requested() => {
+				shutdown = true;
+				break;
+			}
+		};
+		let data = match message {
+			Some(Ok(data)) => data,
+			Some(Err(error)) => {
+				log::error!("Failed to read from WebSocket: {}", error);
+				break;
+			}
+			None => break,
 		};
 
 		if let Message::Text(text) = data {
-			let decoded: InboundEventType = match serde_json::from_str(&text) {
-				Ok(event) => event,
-				Err(_) => {
-					log::warn!("Unknown event received: {}", text);
+			let value: serde_json::Value = match serde_json::from_str(&text) {
+				Ok(value) => value,
+				Err(error) => {
+					log::warn!("Invalid message received: {} ({})", text, error);
 					continue;
 				}
 			};
-
-			if let Err(error) = match decoded {
-				InboundEventType::SetImage(event) => {
-					if let Some(h) = GLOBAL_EVENT_HANDLER.get() {
-						h.device_plugin_set_image(event).await
-					} else {
-						Ok(())
-					}
-				}
-				InboundEventType::SetBrightness(event) => {
-					if let Some(h) = GLOBAL_EVENT_HANDLER.get() {
-						h.device_plugin_set_brightness(event).await
-					} else {
-						Ok(())
-					}
-				}
-				InboundEventType::DidReceiveGlobalSettings(event) => {
-					if let Some(h) = GLOBAL_EVENT_HANDLER.get() {
-						h.did_receive_global_settings(event).await
-					} else {
-						Ok(())
-					}
-				}
-				InboundEventType::DeviceDidConnect(event) => {
-					crate::runtime::CONNECTED_DEVICES.insert(event.device.clone(), event.deviceInfo.clone());
-					if let Some(h) = GLOBAL_EVENT_HANDLER.get() {
-						h.device_did_connect(event).await
-					} else {
-						Ok(())
-					}
-				}
-				InboundEventType::DeviceDidDisconnect(event) => {
-					crate::runtime::CONNECTED_DEVICES.remove(&event.device);
-					if let Some(h) = GLOBAL_EVENT_HANDLER.get() {
-						h.device_did_disconnect(event).await
-					} else {
-						Ok(())
-					}
-				}
-				InboundEventType::ApplicationDidLaunch(event) => {
-					if let Some(h) = GLOBAL_EVENT_HANDLER.get() {
-						h.application_did_launch(event).await
-					} else {
-						Ok(())
-					}
-				}
-				InboundEventType::ApplicationDidTerminate(event) => {
-					if let Some(h) = GLOBAL_EVENT_HANDLER.get() {
-						h.application_did_terminate(event).await
-					} else {
-						Ok(())
-					}
-				}
-				InboundEventType::DidReceiveDeepLink(event) => {
-					if let Some(h) = GLOBAL_EVENT_HANDLER.get() {
-						h.did_receive_deep_link(event).await
-					} else {
-						Ok(())
-					}
-				}
-				InboundEventType::SystemDidWakeUp(event) => {
-					if let Some(h) = GLOBAL_EVENT_HANDLER.get() {
-						h.system_did_wake_up(event).await
-					} else {
-						Ok(())
+			let decoded = match InboundEventType::deserialize(&value) {
+				Ok(event) => event,
+				Err(error) => match value.get("event").and_then(|event| event.as_str()) {
+					Some(event) if KNOWN_EVENTS.contains(&event) => {
+						log::error!("Failed to parse {} event: {} ({})", event, error, text);
+						continue;
 					}
+					_ => InboundEventType::Unknown(value),
+				},
+			};
+
+			match &mut lanes {
+				Some(lanes) => lanes.dispatch(decoded),
+				None => dispatch(runtime, decoded).await,
+			}
+		}
+	}
+
+	if let Some(lanes) = lanes {
+		lanes.finish().await;
+	}
+	shutdown
+}
+
+impl InboundEventType {
+	/// The instance this event relates to, or `None` for global events
+	fn context(&self) -> Option<&str> {
+		match self {
+			Self::KeyDown(event) | Self::KeyUp(event) => Some(&event.context),
+			Self::DialDown(event) | Self::DialUp(event) => Some(&event.context),
+			Self::DialRotate(event) => Some(&event.context),
+			Self::TouchTap(event) => Some(&event.context),
+			Self::DidReceiveSettings(event) => Some(&event.context),
+			Self::WillAppear(event) | Self::WillDisappear(event) => Some(&event.context),
+			Self::TitleParametersDidChange(event) => Some(&event.context),
+			Self::PropertyInspectorDidAppear(event) | Self::PropertyInspectorDidDisappear(event) => {
+				Some(&event.context)
+			}
+			Self::SendToPlugin(event) => Some(&event.context),
+			_ => None,
+		}
+	}
+}
+
+async fn dispatch(runtime: &Arc<Runtime>, decoded: InboundEventType) {
+	// Timeouts are applied to each event handler, using the action's timeout for instance events
+	if let Err(error) = catch_panic(dispatch_inner(runtime, decoded)).await {
+		log::error!("Failed to process inbound event: {}", error)
+	}
+}
+
+async fn dispatch_inner(runtime: &Arc<Runtime>, decoded: InboundEventType) -> Result<()> {
+	match decoded {
+		InboundEventType::SetImage(event) => {
+			runtime.events.publish(|| Event::SetImage(event.clone()));
+			notify!(runtime, "setImage", device_plugin_set_image, event)
+		}
+		InboundEventType::SetBrightness(event) => {
+			runtime.events.publish(|| Event::SetBrightness(event.clone()));
+			notify!(runtime, "setBrightness", device_plugin_set_brightness, event)
+		}
+		InboundEventType::DidReceiveGlobalSettings(event) => {
+			runtime
+				.handle_did_receive_global_settings(&event.payload.settings)
+				.await;
+			runtime
+				.events
+				.publish(|| Event::DidReceiveGlobalSettings(event.clone()));
+			notify!(runtime, "didReceiveGlobalSettings", did_receive_global_settings, event)
+		}
+		InboundEventType::DeviceDidConnect(event) => {
+			runtime.devices.insert(event.device.clone(), event.deviceInfo.clone());
+			runtime.events.publish(|| Event::DeviceDidConnect(event.clone()));
+			notify!(runtime, "deviceDidConnect", device_did_connect, event)
+		}
+		InboundEventType::DeviceDidDisconnect(event) => {
+			runtime.devices.remove(&event.device);
+			runtime.events.publish(|| Event::DeviceDidDisconnect(event.clone()));
+			notify!(runtime, "deviceDidDisconnect", device_did_disconnect, event)
+		}
+		InboundEventType::ApplicationDidLaunch(event) => {
+			runtime.events.publish(|| Event::ApplicationDidLaunch(event.clone()));
+			notify!(runtime, "applicationDidLaunch", application_did_launch, event)
+		}
+		InboundEventType::ApplicationDidTerminate(event) => {
+			runtime.events.publish(|| Event::ApplicationDidTerminate(event.clone()));
+			notify!(runtime, "applicationDidTerminate", application_did_terminate, event)
+		}
+		InboundEventType::DidReceiveDeepLink(event) => {
+			runtime.events.publish(|| Event::DidReceiveDeepLink(event.clone()));
+			notify!(runtime, "didReceiveDeepLink", did_receive_deep_link, event)
+		}
+		InboundEventType::SystemDidWakeUp(event) => {
+			runtime.events.publish(|| Event::SystemDidWakeUp(event));
+			notify!(runtime, "systemDidWakeUp", system_did_wake_up, event)
+		}
+		InboundEventType::Unknown(value) => {
+			runtime.events.publish(|| Event::Unknown(value.clone()));
+			let value = &value;
+			let timeout = *runtime.handler_timeout.lock().await;
+			runtime
+				.global_event_handlers
+				.each(
+					"rawEvent",
+					timeout,
+					|handler| async move { handler.raw_event(value).await },
+				)
+				.await;
+			let name = value.get("event").and_then(|name| name.as_str()).unwrap_or_default();
+			match runtime.custom_events.get(name).map(|handler| handler.clone()) {
+				Some(handler) => with_timeout(timeout, handler.call(value.clone())).await,
+				None => {
+					log::warn!("Unknown event received: {}", value);
+					Ok(())
 				}
-				/* Instance events */
-				InboundEventType::KeyDown(event) => runtime::handle_key_down(event).await,
-				InboundEventType::KeyUp(event) => runtime::handle_key_up(event).await,
-				InboundEventType::DialDown(event) => runtime::handle_dial_down(event).await,
-				InboundEventType::DialUp(event) => runtime::handle_dial_up(event).await,
-				InboundEventType::DialRotate(event) => runtime::handle_dial_rotate(event).await,
-				InboundEventType::TouchTap(event) => runtime::handle_touch_tap(event).await,
-				InboundEventType::DidReceiveSettings(event) => runtime::handle_did_receive_settings(event).await,
-				InboundEventType::WillAppear(event) => crate::runtime::handle_will_appear(event).await,
-				InboundEventType::WillDisappear(event) => crate::runtime::handle_will_disappear(event).await,
-				InboundEventType::TitleParametersDidChange(event) => {
-					runtime::handle_title_parameters_did_change(event).await
-				}
-				InboundEventType::PropertyInspectorDidAppear(event) => {
-					runtime::handle_property_inspector_did_appear(event).await
-				}
-				InboundEventType::PropertyInspectorDidDisappear(event) => {
-					runtime::handle_property_inspector_did_disappear(event).await
-				}
-				InboundEventType::SendToPlugin(event) => runtime::handle_send_to_plugin(event).await,
-			} {
-				log::error!("Failed to process inbound event: {}", error)
 			}
 		}
+		/* Instance events */
+		InboundEventType::KeyDown(event) => runtime.handle_key_down(event).await,
+		InboundEventType::KeyUp(event) => runtime.handle_key_up(event).await,
+		InboundEventType::DialDown(event) => runtime.handle_dial_down(event).await,
+		InboundEventType::DialUp(event) => runtime.handle_dial_up(event).await,
+		InboundEventType::DialRotate(event) => runtime.handle_dial_rotate(event).await,
+		InboundEventType::TouchTap(event) => runtime.handle_touch_tap(event).await,
+		InboundEventType::DidReceiveSettings(event) => runtime.handle_did_receive_settings(event).await,
+		InboundEventType::WillAppear(event) => runtime.handle_will_appear(event).await,
+		InboundEventType::WillDisappear(event) => runtime.handle_will_disappear(event).await,
+		InboundEventType::TitleParametersDidChange(event) => runtime.handle_title_parameters_did_change(event).await,
+		InboundEventType::PropertyInspectorDidAppear(event) => {
+			runtime.handle_property_inspector_did_appear(event).await
+		}
+		InboundEventType::PropertyInspectorDidDisappear(event) => {
+			runtime.handle_property_inspector_did_disappear(event).await
+		}
+		InboundEventType::SendToPlugin(event) => runtime.handle_send_to_plugin(event).await,
 	}
 }
@@ -2,8 +2,52 @@ use super::DeviceInfo;
 
 use serde::Deserialize;
 
+/// Information about the application running the plugin
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ApplicationInfo {
+	pub font: String,
+	pub language: String,
+	pub platform: String,
+	pub platform_version: String,
+	pub version: String,
+}
+
+/// Information about the plugin as defined in the plugin manifest
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PluginInfo {
+	pub uuid: String,
+	pub version: String,
+}
+
+/// Colors of the application's theme
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ThemeColors {
+	pub button_pressed_background_color: String,
+	pub button_pressed_border_color: String,
+	pub button_pressed_text_color: String,
+	pub disabled_color: String,
+	pub highlight_color: String,
+	pub mouse_down_color: String,
+}
+
+/// The registration information passed to the plugin with the `-info` flag
 #[derive(Clone, Debug, Deserialize)]
-pub struct Info {
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationInfo {
+	#[serde(default)]
+	pub application: ApplicationInfo,
+	#[serde(default)]
+	pub plugin: PluginInfo,
+	#[serde(default = "default_device_pixel_ratio")]
+	pub device_pixel_ratio: f64,
+	#[serde(default)]
+	pub colors: ThemeColors,
 	pub devices: Vec<DeviceInfo>,
-	// Other unused fields...
+}
+
+fn default_device_pixel_ratio() -> f64 {
+	1.0
 }
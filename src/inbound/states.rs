@@ -23,6 +23,8 @@ pub struct TitleParametersDidChangePayload {
 	pub state: u16,
 	pub title: String,
 	pub titleParameters: TitleParameters,
+	#[serde(default, rename = "isInMultiAction")]
+	pub is_in_multi_action: bool,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -0,0 +1,111 @@
+use super::GlobalEventHandler;
+
+use crate::OpenActionResult as Result;
+use crate::runtime::{Runtime, catch_panic, with_timeout};
+
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+
+#[derive(Clone)]
+pub(super) enum Subscriber {
+	Static(&'static dyn GlobalEventHandler),
+	Shared(Arc<dyn GlobalEventHandler>),
+}
+
+impl Deref for Subscriber {
+	type Target = dyn GlobalEventHandler;
+
+	fn deref(&self) -> &Self::Target {
+		match self {
+			Self::Static(handler) => *handler,
+			Self::Shared(handler) => &**handler,
+		}
+	}
+}
+
+/// Global event handlers of a plugin, in the order they were registered
+#[derive(Default)]
+pub(crate) struct Subscribers {
+	handlers: RwLock<Vec<(u64, Subscriber)>>,
+	next_id: AtomicU64,
+	static_set: AtomicBool,
+}
+
+impl Subscribers {
+	fn push(&self, handler: Subscriber) -> u64 {
+		let id = self.next_id.fetch_add(1, Relaxed);
+		self.handlers.write().unwrap().push((id, handler));
+		id
+	}
+
+	/// Add the handler registered with [`set_global_event_handler`](super::set_global_event_handler), unless one has
+	/// already been added
+	pub(crate) fn set_static(&self, handler: &'static dyn GlobalEventHandler) {
+		if !self.static_set.swap(true, Relaxed) {
+			self.push(Subscriber::Static(handler));
+		}
+	}
+
+	/// Add a handler that stays subscribed for the lifetime of the plugin
+	pub(crate) fn add(&self, handler: Arc<dyn GlobalEventHandler>) {
+		self.push(Subscriber::Shared(handler));
+	}
+
+	pub(crate) fn subscribe(runtime: &Arc<Runtime>, handler: Arc<dyn GlobalEventHandler>) -> GlobalEventSubscription {
+		let id = runtime.global_event_handlers.push(Subscriber::Shared(handler));
+		GlobalEventSubscription {
+			runtime: Arc::downgrade(runtime),
+			id,
+		}
+	}
+
+	fn unsubscribe(&self, id: u64) {
+		self.handlers
+			.write()
+			.unwrap()
+			.retain(|(handler_id, _)| *handler_id != id);
+	}
+
+	/// Call a method of every handler in registration order, logging failures without stopping at them
+	///
+	/// Each handler is given the whole timeout, so a slow handler does not cut short the ones after it.
+	pub(super) async fn each<F, Fut>(&self, name: &str, timeout: Option<Duration>, f: F)
+	where
+		F: Fn(Subscriber) -> Fut,
+		Fut: Future<Output = Result<()>>,
+	{
+		let handlers: Vec<_> = self.handlers.read().unwrap().iter().map(|(_, h)| h.clone()).collect();
+		for handler in handlers {
+			if let Err(error) = with_timeout(timeout, catch_panic(f(handler))).await {
+				log::error!("Failed to run global event handler ({}): {}", name, error);
+			}
+		}
+	}
+}
+
+/// A subscription to global events, which unsubscribes the handler when dropped
+///
+/// Use [`detach`](Self::detach) to keep the handler subscribed for the lifetime of the plugin.
+#[must_use = "the handler is unsubscribed when the subscription is dropped"]
+pub struct GlobalEventSubscription {
+	runtime: Weak<Runtime>,
+	id: u64,
+}
+
+impl GlobalEventSubscription {
+	/// Keep the handler subscribed for the lifetime of the plugin
+	pub fn detach(self) {
+		std::mem::forget(self);
+	}
+}
+
+impl Drop for GlobalEventSubscription {
+	fn drop(&mut self) {
+		if let Some(runtime) = self.runtime.upgrade() {
+			runtime.global_event_handlers.unsubscribe(self.id);
+		}
+	}
+}
@@ -1,24 +1,45 @@
+mod args;
 mod inbound;
 mod outbound;
 mod runtime;
 
-pub use crate::inbound::{Coordinates, TitleParametersDidChangePayload};
+pub use crate::args::PluginArgs;
+pub use crate::inbound::{
+	ApplicationInfo, Controller, Coordinates, DeviceInfo, DeviceSizeInfo, DeviceType, DispatchMode, PluginInfo,
+	RegistrationInfo, ThemeColors, TitleParametersDidChangePayload,
+};
+pub use crate::outbound::TriggerDescription;
 pub use crate::runtime::*;
 
 /// Events that do not relate to a specific instance of an action
 pub mod global_events {
+	pub use super::inbound::CustomEventHandler;
+	pub use super::inbound::{
+		ApplicationEvent, ApplicationPayload, DidReceiveDeepLinkEvent, DidReceiveDeepLinkPayload,
+		DidReceiveGlobalSettingsPayload,
+	};
 	pub use super::inbound::{
 		DeviceDidConnectEvent, DeviceDidDisconnectEvent, DidReceiveGlobalSettingsEvent, GlobalEventHandler,
-		SetBrightnessEvent, SetImageEvent, SystemDidWakeUpEvent, set_global_event_handler,
+		GlobalEventSubscription, SetBrightnessEvent, SetImageEvent, SystemDidWakeUpEvent, set_global_event_handler,
+		subscribe_global_events,
 	};
 }
 
+/// A stream of inbound events, as an alternative to implementing [`Action`] and
+/// [`GlobalEventHandler`](global_events::GlobalEventHandler)
+pub mod events {
+	pub use super::inbound::{
+		AppearEvent, DialPressEvent, DialPressPayload, DialRotateEvent, DialRotatePayload, DidReceiveSettingsEvent,
+		Event, GenericInstancePayload, KeyEvent, PropertyInspectorAppearEvent, SendToPluginEvent,
+		TitleParametersDidChangeEvent, TouchTapEvent, TouchTapPayload,
+	};
+	pub use super::runtime::events::events;
+}
+
 /// [`mod@async_trait`]
 pub use async_trait::async_trait;
 
-use futures_util::StreamExt;
 use thiserror::Error;
-use tokio_tungstenite::connect_async;
 
 #[derive(Debug, Error)]
 pub enum OpenActionError {
@@ -27,12 +48,74 @@ pub enum OpenActionError {
 
 	#[error("serialization or deserialization error: {0}")]
 	Serde(#[from] serde_json::Error),
+
+	#[error("invalid plugin arguments: {0}")]
+	InvalidArgs(String),
+
+	#[error("timed out waiting for {0}")]
+	ResponseTimeout(&'static str),
+
+	#[error("not connected to the OpenAction server")]
+	NotConnected,
+
+	/// An event handler did not finish within the configured timeout
+	#[error("event handler timed out after {0:?}")]
+	Timeout(std::time::Duration),
+
+	/// An event handler panicked with the given message
+	#[error("event handler panicked: {0}")]
+	Panic(String),
+
+	/// An error returned by an event handler
+	#[error("{0}")]
+	Handler(#[from] Box<dyn std::error::Error + Send + Sync>),
+
+	/// An error returned by an event handler of an action instance
+	#[error("{event} handler of action '{action}' failed for instance '{instance}': {source}")]
+	Instance {
+		action: String,
+		instance: InstanceId,
+		event: &'static str,
+		source: Box<OpenActionError>,
+	},
+
+	/// A message for the property inspector of one action was sent to an instance of another action
+	#[error("message for the property inspector of action '{expected}' sent to an instance of action '{action}'")]
+	ActionMismatch { expected: &'static str, action: String },
+}
+
+impl OpenActionError {
+	/// Wrap an arbitrary error returned by an event handler
+	pub fn handler(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+		Self::Handler(error.into())
+	}
+}
+
+impl From<std::io::Error> for OpenActionError {
+	fn from(error: std::io::Error) -> Self {
+		Self::handler(error)
+	}
+}
+
+impl From<String> for OpenActionError {
+	fn from(error: String) -> Self {
+		Self::handler(error)
+	}
+}
+
+impl From<&str> for OpenActionError {
+	fn from(error: &str) -> Self {
+		Self::handler(error)
+	}
 }
 
 pub type OpenActionResult<T> = Result<T, OpenActionError>;
 
-/// Register the plugin and run the plugin event loop, blocking until disconnect
-/// ```rust
+/// Register the plugin and run the plugin event loop, reconnecting according to the [`ReconnectPolicy`]
+/// and blocking until the connection is lost for good or a shutdown is requested through a [`PluginHandle`]
+///
+/// Fails immediately if the first connection to the OpenAction server cannot be established.
+/// ```rust,no_run
 /// use openaction::*;
 ///
 /// #[tokio::main]
@@ -43,31 +126,12 @@ pub type OpenActionResult<T> = Result<T, OpenActionError>;
 /// }
 /// ```
 pub async fn run(args: Vec<String>) -> OpenActionResult<()> {
-	let lookup = |flag: &str| -> String {
-		let i = args
-			.iter()
-			.position(|x| x.to_lowercase().trim() == flag)
-			.unwrap_or_else(|| panic!("missing CLI flag: {}", flag));
-		args.get(i + 1)
-			.cloned()
-			.unwrap_or_else(|| panic!("missing CLI flag value for flag: {}", flag))
-	};
-	let port = lookup("-port");
-	let uuid = lookup("-pluginuuid");
-	let event = lookup("-registerevent");
-	let info: inbound::registration::Info = serde_json::from_str(&lookup("-info"))?;
-	for device in info.devices {
-		runtime::CONNECTED_DEVICES.insert(device.id.clone(), device);
-	}
-
-	let socket = connect_async(format!("ws://localhost:{}", port)).await?.0;
-	let (write, read) = socket.split();
-
-	let mut outbound = outbound::OutboundEventManager::new(write, uuid);
-	outbound.register(event).await?;
-	runtime::set_outbound_manager(outbound).await;
-
-	inbound::process_incoming_messages(read).await;
+	default_plugin().run(args).await
+}
 
-	Ok(())
+/// Register the plugin and run the plugin event loop using already-parsed [`PluginArgs`]
+///
+/// See [`run`] for details.
+pub async fn run_with_args(args: PluginArgs) -> OpenActionResult<()> {
+	default_plugin().run_with_args(args).await
 }
@@ -37,8 +37,8 @@ pub struct TouchscreenPressPayload {
 }
 
 impl OutboundEventManager {
-	pub async fn register_device(
-		&mut self,
+	pub fn register_device(
+		&self,
 		id: String,
 		name: String,
 		rows: u8,
@@ -57,42 +57,37 @@ impl OutboundEventManager {
 				r#type,
 			},
 		})
-		.await
 	}
 
-	pub async fn deregister_device(&mut self, id: String) -> Result<()> {
+	pub fn deregister_device(&self, id: String) -> Result<()> {
 		self.send_event(PayloadEvent {
 			event: "deregisterDevice",
 			payload: id,
 		})
-		.await
 	}
 
-	pub async fn rerender_images(&mut self, id: String) -> Result<()> {
+	pub fn rerender_images(&self, id: String) -> Result<()> {
 		self.send_event(PayloadEvent {
 			event: "rerenderImages",
 			payload: id,
 		})
-		.await
 	}
 
-	pub async fn key_down(&mut self, device: String, position: u8) -> Result<()> {
+	pub fn key_down(&self, device: String, position: u8) -> Result<()> {
 		self.send_event(PayloadEvent {
 			event: "keyDown",
 			payload: PressPayload { device, position },
 		})
-		.await
 	}
 
-	pub async fn key_up(&mut self, device: String, position: u8) -> Result<()> {
+	pub fn key_up(&self, device: String, position: u8) -> Result<()> {
 		self.send_event(PayloadEvent {
 			event: "keyUp",
 			payload: PressPayload { device, position },
 		})
-		.await
 	}
 
-	pub async fn encoder_change(&mut self, device: String, position: u8, ticks: i16) -> Result<()> {
+	pub fn encoder_change(&self, device: String, position: u8, ticks: i16) -> Result<()> {
 		self.send_event(PayloadEvent {
 			event: "encoderChange",
 			payload: TicksPayload {
@@ -101,26 +96,23 @@ impl OutboundEventManager {
 				ticks,
 			},
 		})
-		.await
 	}
 
-	pub async fn encoder_down(&mut self, device: String, position: u8) -> Result<()> {
+	pub fn encoder_down(&self, device: String, position: u8) -> Result<()> {
 		self.send_event(PayloadEvent {
 			event: "encoderDown",
 			payload: PressPayload { device, position },
 		})
-		.await
 	}
 
-	pub async fn encoder_up(&mut self, device: String, position: u8) -> Result<()> {
+	pub fn encoder_up(&self, device: String, position: u8) -> Result<()> {
 		self.send_event(PayloadEvent {
 			event: "encoderUp",
 			payload: PressPayload { device, position },
 		})
-		.await
 	}
 
-	pub async fn touchscreen_press(&mut self, device: String, position: u8, x: u16, y: u16, hold: bool) -> Result<()> {
+	pub fn touchscreen_press(&self, device: String, position: u8, x: u16, y: u16, hold: bool) -> Result<()> {
 		self.send_event(PayloadEvent {
 			event: "touchscreenPress",
 			payload: TouchscreenPressPayload {
@@ -131,6 +123,5 @@ impl OutboundEventManager {
 				hold,
 			},
 		})
-		.await
 	}
 }
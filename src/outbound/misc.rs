@@ -10,6 +10,22 @@ struct RegisterEvent {
 	uuid: String,
 }
 
+#[derive(Serialize)]
+struct SwitchToProfileEvent {
+	event: &'static str,
+	context: String,
+	device: String,
+	payload: SwitchToProfilePayload,
+}
+
+#[derive(Serialize)]
+struct SwitchToProfilePayload {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	profile: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	page: Option<u16>,
+}
+
 #[derive(Serialize)]
 struct OpenUrlPayload {
 	url: String,
@@ -21,36 +37,41 @@ struct LogMessagePayload {
 }
 
 impl OutboundEventManager {
-	pub(crate) async fn register(&mut self, event: String) -> Result<()> {
+	pub(crate) fn register(&self, event: String) -> Result<()> {
 		self.send_event(RegisterEvent {
 			event,
 			uuid: self.uuid.clone(),
 		})
-		.await
 	}
 
-	pub async fn open_url(&mut self, url: String) -> Result<()> {
+	pub fn open_url(&self, url: String) -> Result<()> {
 		self.send_event(PayloadEvent {
 			event: "openUrl",
 			payload: OpenUrlPayload { url },
 		})
-		.await
 	}
 
-	pub async fn log_message(&mut self, message: String) -> Result<()> {
+	pub fn log_message(&self, message: String) -> Result<()> {
 		self.send_event(PayloadEvent {
 			event: "logMessage",
 			payload: LogMessagePayload { message },
 		})
-		.await
 	}
 
-	pub async fn send_to_property_inspector(&mut self, context: String, payload: serde_json::Value) -> Result<()> {
+	pub fn switch_to_profile(&self, device: String, profile: Option<String>, page: Option<u16>) -> Result<()> {
+		self.send_event(SwitchToProfileEvent {
+			event: "switchToProfile",
+			context: self.uuid.clone(),
+			device,
+			payload: SwitchToProfilePayload { profile, page },
+		})
+	}
+
+	pub fn send_to_property_inspector(&self, context: String, payload: serde_json::Value) -> Result<()> {
 		self.send_event(ContextAndPayloadEvent {
 			event: "sendToPropertyInspector",
 			context,
 			payload,
 		})
-		.await
 	}
 }
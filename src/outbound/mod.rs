@@ -3,32 +3,87 @@ mod misc;
 mod settings;
 mod states;
 
+pub use states::TriggerDescription;
+
 use crate::OpenActionResult as Result;
 
 use futures_util::{SinkExt, stream::SplitSink};
 use serde::Serialize;
-use tokio_tungstenite::tungstenite::Message;
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::{Error as WebSocketError, Message};
 
 type Sink =
 	SplitSink<tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>, Message>;
 
+enum Outgoing {
+	Message(Message),
+	Flush(oneshot::Sender<Result<()>>),
+	Close(oneshot::Sender<Result<()>>),
+}
+
 /// A struct with methods for sending events to the OpenAction server
+///
+/// Events are queued and written to the WebSocket by a dedicated writer task, which closes the connection once
+/// every clone of the manager has been dropped.
+#[derive(Clone)]
 pub(crate) struct OutboundEventManager {
-	sink: Sink,
+	sender: mpsc::UnboundedSender<Outgoing>,
 	uuid: String,
 }
 
 impl OutboundEventManager {
 	pub(crate) fn new(sink: Sink, uuid: String) -> Self {
-		Self { sink, uuid }
+		let (sender, receiver) = mpsc::unbounded_channel();
+		tokio::spawn(write_outgoing(sink, receiver));
+		Self { sender, uuid }
 	}
 
-	pub async fn send_event(&mut self, event: impl Serialize) -> Result<()> {
-		self.sink
-			.send(Message::Text(serde_json::to_string(&event)?.into()))
-			.await?;
+	pub fn send_event(&self, event: impl Serialize) -> Result<()> {
+		let message = Message::Text(serde_json::to_string(&event)?.into());
+		self.sender
+			.send(Outgoing::Message(message))
+			.map_err(|_| WebSocketError::AlreadyClosed)?;
 		Ok(())
 	}
+
+	/// Wait until every event queued so far has been written to the WebSocket
+	pub async fn flush(&self) -> Result<()> {
+		let (sender, receiver) = oneshot::channel();
+		self.sender
+			.send(Outgoing::Flush(sender))
+			.map_err(|_| WebSocketError::AlreadyClosed)?;
+		receiver.await.map_err(|_| WebSocketError::AlreadyClosed)?
+	}
+
+	/// Write every event queued so far, then close the WebSocket with a Close frame
+	pub(crate) async fn close(self) -> Result<()> {
+		let (sender, receiver) = oneshot::channel();
+		self.sender
+			.send(Outgoing::Close(sender))
+			.map_err(|_| WebSocketError::AlreadyClosed)?;
+		receiver.await.map_err(|_| WebSocketError::AlreadyClosed)?
+	}
+}
+
+async fn write_outgoing(mut sink: Sink, mut receiver: mpsc::UnboundedReceiver<Outgoing>) {
+	while let Some(outgoing) = receiver.recv().await {
+		match outgoing {
+			Outgoing::Message(message) => {
+				if let Err(error) = sink.send(message).await {
+					log::error!("Failed to write to WebSocket: {}", error);
+					return;
+				}
+			}
+			Outgoing::Flush(reply) => {
+				let _ = reply.send(sink.flush().await.map_err(Into::into));
+			}
+			Outgoing::Close(reply) => {
+				let _ = reply.send(sink.close().await.map_err(Into::into));
+				return;
+			}
+		}
+	}
+	let _ = sink.close().await;
 }
 
 #[derive(Serialize)]
@@ -26,65 +26,82 @@ struct SetFeedbackLayoutPayload {
 	layout: String,
 }
 
+/// Descriptions of the interactions of an encoder, shown on the device where supported
+///
+/// Fields left as `None` are reset to the description in the plugin manifest.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TriggerDescription {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub long_touch: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub push: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub rotate: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub touch: Option<String>,
+}
+
 impl OutboundEventManager {
-	pub async fn set_title(&mut self, context: String, title: Option<String>, state: Option<u16>) -> Result<()> {
+	pub fn set_title(&self, context: String, title: Option<String>, state: Option<u16>) -> Result<()> {
 		self.send_event(ContextAndPayloadEvent {
 			event: "setTitle",
 			context,
 			payload: SetTitlePayload { title, state },
 		})
-		.await
 	}
 
-	pub async fn set_image(&mut self, context: String, image: Option<String>, state: Option<u16>) -> Result<()> {
+	pub fn set_image(&self, context: String, image: Option<String>, state: Option<u16>) -> Result<()> {
 		self.send_event(ContextAndPayloadEvent {
 			event: "setImage",
 			context,
 			payload: SetImagePayload { image, state },
 		})
-		.await
 	}
 
-	pub async fn set_state(&mut self, context: String, state: u16) -> Result<()> {
+	pub fn set_state(&self, context: String, state: u16) -> Result<()> {
 		self.send_event(ContextAndPayloadEvent {
 			event: "setState",
 			context,
 			payload: SetStatePayload { state },
 		})
-		.await
 	}
 
-	pub async fn set_feedback(&mut self, context: String, feedback: &impl serde::Serialize) -> Result<()> {
+	pub fn set_feedback(&self, context: String, feedback: &impl serde::Serialize) -> Result<()> {
 		self.send_event(ContextAndPayloadEvent {
 			event: "setFeedback",
 			context,
 			payload: feedback,
 		})
-		.await
 	}
 
-	pub async fn set_feedback_layout(&mut self, context: String, layout: String) -> Result<()> {
+	pub fn set_feedback_layout(&self, context: String, layout: String) -> Result<()> {
 		self.send_event(ContextAndPayloadEvent {
 			event: "setFeedbackLayout",
 			context,
 			payload: SetFeedbackLayoutPayload { layout },
 		})
-		.await
 	}
 
-	pub async fn show_alert(&mut self, context: String) -> Result<()> {
+	pub fn set_trigger_description(&self, context: String, description: TriggerDescription) -> Result<()> {
+		self.send_event(ContextAndPayloadEvent {
+			event: "setTriggerDescription",
+			context,
+			payload: description,
+		})
+	}
+
+	pub fn show_alert(&self, context: String) -> Result<()> {
 		self.send_event(ContextEvent {
 			event: "showAlert",
 			context,
 		})
-		.await
 	}
 
-	pub async fn show_ok(&mut self, context: String) -> Result<()> {
+	pub fn show_ok(&self, context: String) -> Result<()> {
 		self.send_event(ContextEvent {
 			event: "showOk",
 			context,
 		})
-		.await
 	}
 }
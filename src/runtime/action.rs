@@ -1,12 +1,17 @@
 use super::instance::Instance;
+use super::{ErrorPolicy, InvalidSettingsPolicy};
 
-use crate::OpenActionResult as Result;
+use crate::{OpenActionError, OpenActionResult as Result};
+
+use std::time::Duration;
 
 use async_trait::async_trait;
 use serde::{Serialize, de::DeserializeOwned};
 
 #[async_trait]
 /// Event handler trait for events that relate to a specific instance of an action
+///
+/// Key events are handled by [`KeypadAction`] and dial and touch strip events by [`EncoderAction`].
 pub trait Action: Send + Sync + 'static {
 	/// Action UUID as defined in the plugin manifest
 	const UUID: &'static str;
@@ -14,6 +19,62 @@ pub trait Action: Send + Sync + 'static {
 	/// Settings type for this action
 	type Settings: Serialize + DeserializeOwned + Default + Send + Sync + 'static;
 
+	/// Migrations applied in order to settings saved by older versions of this action before they are deserialized
+	///
+	/// Settings are stamped with the number of migrations applied to them in the reserved `_settingsVersion` field,
+	/// so only append to this list. Migrated settings are written back to the OpenAction server. Empty settings, as
+	/// given to a newly created instance, are stamped with the latest version without being migrated.
+	///
+	/// The field is removed before the settings are deserialized, so it does not conflict with
+	/// `#[serde(deny_unknown_fields)]`. When settings without it are received with `didReceiveSettings`, such as an
+	/// object saved by the property inspector, the version of the previous settings is kept and written back to the
+	/// OpenAction server, so migrations are not applied twice. Settings without it in any other event are treated as
+	/// unmigrated.
+	const SETTINGS_MIGRATIONS: &'static [fn(serde_json::Value) -> serde_json::Value] = &[];
+
+	/// How errors returned by this action's event handlers are handled, or `None` to use the policy set with
+	/// [`set_error_policy`](crate::set_error_policy)
+	fn error_policy(&self) -> Option<ErrorPolicy> {
+		None
+	}
+
+	/// Maximum time this action's event handlers may run, or `None` to use the timeout set with
+	/// [`set_handler_timeout`](crate::set_handler_timeout)
+	///
+	/// Return [`Duration::MAX`] to disable the timeout for this action only.
+	fn handler_timeout(&self) -> Option<Duration> {
+		None
+	}
+
+	/// What happens when the settings of an instance cannot be deserialized as [`Self::Settings`]
+	fn invalid_settings_policy(&self) -> InvalidSettingsPolicy {
+		InvalidSettingsPolicy::UseDefault
+	}
+
+	/// Called when the settings of an instance cannot be deserialized, if the policy is
+	/// [`InvalidSettingsPolicy::Hook`], returning the settings to pass to the event handler or `None` to skip it
+	///
+	/// Returned settings are used for later events as well, until the settings of the instance change.
+	async fn invalid_settings(
+		&self,
+		_instance: &Instance,
+		_raw: &serde_json::Value,
+		_error: &serde_json::Error,
+	) -> Result<Option<Self::Settings>> {
+		Ok(None)
+	}
+
+	/// Called after any other event handler of this action returns an error
+	async fn on_error(&self, _instance: &Instance, _error: &OpenActionError) -> Result<()> {
+		Ok(())
+	}
+
+	/// Called when an inbound event carries settings that differ from the previous settings of the instance, before
+	/// the event's own handler
+	async fn settings_changed(&self, _instance: &Instance, _old: &Self::Settings, _new: &Self::Settings) -> Result<()> {
+		Ok(())
+	}
+
 	/// <https://openaction.amankhanna.me/4_clientbound/will_appear.html#willappear>
 	async fn will_appear(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
 		Ok(())
@@ -24,78 +85,90 @@ pub trait Action: Send + Sync + 'static {
 		Ok(())
 	}
 
-	/// <https://openaction.amankhanna.me/4_clientbound/keypad.html#keydown>
-	async fn key_down(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
-		Ok(())
-	}
-
-	/// <https://openaction.amankhanna.me/4_clientbound/keypad.html#keyup>
-	async fn key_up(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
+	/// <https://openaction.amankhanna.me/4_clientbound/settings.html#didreceivesettings>
+	async fn did_receive_settings(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
 		Ok(())
 	}
 
-	/// <https://openaction.amankhanna.me/4_clientbound/encoder.html#dialrotate>
-	async fn dial_rotate(
+	/// <https://openaction.amankhanna.me/4_clientbound/states.html#titleparametersdidchange>
+	async fn title_parameters_did_change(
 		&self,
 		_instance: &Instance,
 		_settings: &Self::Settings,
-		_ticks: i16,
-		_pressed: bool,
+		_event: &crate::inbound::TitleParametersDidChangePayload,
 	) -> Result<()> {
 		Ok(())
 	}
 
-	/// <https://openaction.amankhanna.me/4_clientbound/encoder.html#dialdown>
-	async fn dial_down(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
+	/// <https://openaction.amankhanna.me/4_clientbound/property_inspector.html#propertyinspectordidappear>
+	async fn property_inspector_did_appear(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
 		Ok(())
 	}
 
-	/// <https://openaction.amankhanna.me/4_clientbound/encoder.html#dialup>
-	async fn dial_up(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
+	/// <https://openaction.amankhanna.me/4_clientbound/property_inspector.html#propertyinspectordiddisappear>
+	async fn property_inspector_did_disappear(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
 		Ok(())
 	}
 
-	/// <https://openaction.amankhanna.me/4_clientbound/encoder.html#touchtap>
-	async fn touch_tap(
+	async fn send_to_plugin(
 		&self,
 		_instance: &Instance,
 		_settings: &Self::Settings,
-		_position: (u16, u16),
-		_hold: bool,
+		_payload: &serde_json::Value,
 	) -> Result<()> {
 		Ok(())
 	}
+}
 
-	/// <https://openaction.amankhanna.me/4_clientbound/settings.html#didreceivesettings>
-	async fn did_receive_settings(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
+#[async_trait]
+/// Event handlers for actions that can be placed on keys
+///
+/// Register actions that only implement this trait with [`register_keypad_action`](crate::register_keypad_action).
+pub trait KeypadAction: Action {
+	/// <https://openaction.amankhanna.me/4_clientbound/keypad.html#keydown>
+	async fn key_down(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
 		Ok(())
 	}
 
-	/// <https://openaction.amankhanna.me/4_clientbound/states.html#titleparametersdidchange>
-	async fn title_parameters_did_change(
+	/// <https://openaction.amankhanna.me/4_clientbound/keypad.html#keyup>
+	async fn key_up(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
+		Ok(())
+	}
+}
+
+#[async_trait]
+/// Event handlers for actions that can be placed on encoders
+///
+/// Register actions that only implement this trait with [`register_encoder_action`](crate::register_encoder_action).
+pub trait EncoderAction: Action {
+	/// <https://openaction.amankhanna.me/4_clientbound/encoder.html#dialrotate>
+	async fn dial_rotate(
 		&self,
 		_instance: &Instance,
 		_settings: &Self::Settings,
-		_event: &crate::inbound::TitleParametersDidChangePayload,
+		_ticks: i16,
+		_pressed: bool,
 	) -> Result<()> {
 		Ok(())
 	}
 
-	/// <https://openaction.amankhanna.me/4_clientbound/property_inspector.html#propertyinspectordidappear>
-	async fn property_inspector_did_appear(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
+	/// <https://openaction.amankhanna.me/4_clientbound/encoder.html#dialdown>
+	async fn dial_down(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
 		Ok(())
 	}
 
-	/// <https://openaction.amankhanna.me/4_clientbound/property_inspector.html#propertyinspectordiddisappear>
-	async fn property_inspector_did_disappear(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
+	/// <https://openaction.amankhanna.me/4_clientbound/encoder.html#dialup>
+	async fn dial_up(&self, _instance: &Instance, _settings: &Self::Settings) -> Result<()> {
 		Ok(())
 	}
 
-	async fn send_to_plugin(
+	/// <https://openaction.amankhanna.me/4_clientbound/encoder.html#touchtap>
+	async fn touch_tap(
 		&self,
 		_instance: &Instance,
 		_settings: &Self::Settings,
-		_payload: &serde_json::Value,
+		_position: (u16, u16),
+		_hold: bool,
 	) -> Result<()> {
 		Ok(())
 	}
@@ -0,0 +1,83 @@
+use crate::{OpenActionError, OpenActionResult as Result};
+
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+
+use futures_util::FutureExt;
+
+/// What the runtime does when an event handler of an action returns an error, in addition to logging it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ErrorPolicy {
+	/// Show an alert on the instance whose event handler failed
+	pub show_alert: bool,
+	/// Forward the error message to the OpenAction server's log
+	pub log_message: bool,
+}
+
+/// What the runtime does when the settings of an instance cannot be deserialized as the settings type of its action
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidSettingsPolicy {
+	/// Pass the default settings to the event handler
+	#[default]
+	UseDefault,
+	/// Do not call the event handler
+	///
+	/// This includes `willDisappear`, so actions that keep state for each instance must not rely on it to clean up
+	/// after instances with invalid settings.
+	SkipEvent,
+	/// Call [`Action::invalid_settings`](crate::Action::invalid_settings) to decide which settings to use, if any
+	///
+	/// Settings returned by the hook are used until the settings of the instance change.
+	Hook,
+	/// Do not call the event handler, sending an `invalidSettings` message with the error to the property inspector
+	/// once for each invalid value
+	///
+	/// The message is sent again whenever the property inspector appears, as it is dropped while the property
+	/// inspector is closed, and `propertyInspectorDidAppear` handlers are then called with the default settings. Like
+	/// [`SkipEvent`](Self::SkipEvent), this skips `willDisappear`, so actions that keep state for each instance must
+	/// not rely on it to clean up after instances with invalid settings.
+	NotifyPropertyInspector,
+}
+
+/// Set the error policy used for actions that do not override [`Action::error_policy`](crate::Action::error_policy)
+pub async fn set_error_policy(policy: ErrorPolicy) {
+	super::default_plugin().set_error_policy(policy).await
+}
+
+/// Set the default maximum time an event handler may run before it is abandoned, or `None` for no limit (the default)
+///
+/// Applies to each global event handler separately. Individual actions can override this with
+/// [`Action::handler_timeout`](crate::Action::handler_timeout).
+pub async fn set_handler_timeout(timeout: Option<Duration>) {
+	super::default_plugin().set_handler_timeout(timeout).await
+}
+
+/// Run an event handler, failing with [`OpenActionError::Timeout`] if it does not finish within the timeout
+pub(crate) async fn with_timeout(timeout: Option<Duration>, handler: impl Future<Output = Result<()>>) -> Result<()> {
+	match timeout {
+		Some(timeout) => tokio::time::timeout(timeout, handler)
+			.await
+			.unwrap_or(Err(OpenActionError::Timeout(timeout))),
+		None => handler.await,
+	}
+}
+
+/// Run an event handler, converting a panic into an error
+pub(crate) async fn catch_panic(handler: impl Future<Output = Result<()>>) -> Result<()> {
+	AssertUnwindSafe(handler)
+		.catch_unwind()
+		.await
+		.unwrap_or_else(|payload| Err(OpenActionError::Panic(panic_message(payload))))
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+	match payload.downcast::<String>() {
+		Ok(message) => *message,
+		Err(payload) => match payload.downcast::<&'static str>() {
+			Ok(message) => (*message).to_owned(),
+			Err(_) => "unknown panic payload".to_owned(),
+		},
+	}
+}
@@ -0,0 +1,59 @@
+use super::{Plugin, default_plugin};
+
+use crate::inbound::Event;
+
+use futures_util::Stream;
+use tokio::sync::broadcast;
+
+/// How many events may be queued for a subscriber that is not keeping up before the oldest are dropped
+const EVENT_BUFFER: usize = 256;
+
+/// Broadcasts inbound events to the streams returned by [`Plugin::events`]
+pub(crate) struct EventBus(broadcast::Sender<Event>);
+
+impl Default for EventBus {
+	fn default() -> Self {
+		Self(broadcast::Sender::new(EVENT_BUFFER))
+	}
+}
+
+impl EventBus {
+	/// Send an event to every subscribed stream, only constructing it if there are any
+	pub(crate) fn publish(&self, event: impl FnOnce() -> Event) {
+		if self.0.receiver_count() > 0 {
+			let _ = self.0.send(event());
+		}
+	}
+}
+
+impl Plugin {
+	/// Subscribe to inbound events received from now on
+	///
+	/// Events are delivered to the stream before being passed to registered actions and global event handlers. A
+	/// stream that falls more than 256 events behind skips the oldest ones.
+	///
+	/// The stream does not end after [`run`](Self::run) returns, so it should be dropped or raced against the event
+	/// loop. It only ends once every clone of the plugin and every [`PluginHandle`](crate::PluginHandle) to it have
+	/// been dropped, which never happens for the default plugin.
+	pub fn events(&self) -> impl Stream<Item = Event> + Send + 'static {
+		let receiver = self.runtime.events.0.subscribe();
+		futures_util::stream::unfold(receiver, |mut receiver| async move {
+			loop {
+				match receiver.recv().await {
+					Ok(event) => return Some((event, receiver)),
+					Err(broadcast::error::RecvError::Lagged(skipped)) => {
+						log::warn!("Event stream fell behind, skipped {} events", skipped);
+					}
+					Err(broadcast::error::RecvError::Closed) => return None,
+				}
+			}
+		})
+	}
+}
+
+/// Subscribe to inbound events of the default plugin received from now on
+///
+/// See [`Plugin::events`].
+pub fn events() -> impl Stream<Item = Event> + Send + 'static {
+	default_plugin().events()
+}
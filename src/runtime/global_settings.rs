@@ -0,0 +1,171 @@
+use super::{Plugin, Runtime, default_plugin};
+
+use crate::{OpenActionError, OpenActionResult as Result};
+
+use std::any::Any;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::sync::{Mutex, oneshot, watch};
+
+/// How long [`GlobalSettings::update`] waits for the global settings if they have not been loaded yet
+const LOAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub(super) trait ErasedGlobalSettings: Send + Sync {
+	fn receive(&self, value: serde_json::Value);
+	fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync>;
+}
+
+struct Store<T> {
+	runtime: Weak<Runtime>,
+	sender: watch::Sender<Option<Arc<T>>>,
+	update: Mutex<()>,
+}
+
+impl<T> ErasedGlobalSettings for Store<T>
+where
+	T: Serialize + DeserializeOwned + Default + Clone + Send + Sync + 'static,
+{
+	fn receive(&self, value: serde_json::Value) {
+		match serde_json::from_value(value) {
+			Ok(settings) => {
+				self.sender.send_replace(Some(Arc::new(settings)));
+			}
+			Err(error) => log::error!("Failed to deserialize global settings: {}", error),
+		}
+	}
+
+	fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+		self
+	}
+}
+
+/// A typed, cached view of the plugin's global settings
+pub struct GlobalSettings<T>(Arc<Store<T>>);
+
+impl<T> Clone for GlobalSettings<T> {
+	fn clone(&self) -> Self {
+		Self(self.0.clone())
+	}
+}
+
+/// Register the type used for the default plugin's global settings, returning a handle to the cached value
+///
+/// Calling this again with the same type returns a handle to the same cache. Returns `None` if a different type
+/// has already been registered.
+pub async fn register_global_settings<T>() -> Option<GlobalSettings<T>>
+where
+	T: Serialize + DeserializeOwned + Default + Clone + Send + Sync + 'static,
+{
+	default_plugin().register_global_settings().await
+}
+
+impl Plugin {
+	/// Register the type used for this plugin's global settings, returning a handle to the cached value
+	///
+	/// See [`register_global_settings`](crate::register_global_settings).
+	pub async fn register_global_settings<T>(&self) -> Option<GlobalSettings<T>>
+	where
+		T: Serialize + DeserializeOwned + Default + Clone + Send + Sync + 'static,
+	{
+		let mut slot = self.runtime.global_settings.lock().await;
+		match &*slot {
+			Some(store) => store.clone().into_any().downcast::<Store<T>>().ok().map(GlobalSettings),
+			None => {
+				let store = Arc::new(Store {
+					runtime: Arc::downgrade(&self.runtime),
+					sender: watch::Sender::new(None),
+					update: Mutex::new(()),
+				});
+				*slot = Some(store.clone());
+				Some(GlobalSettings(store))
+			}
+		}
+	}
+}
+
+impl Runtime {
+	pub(crate) async fn receive_global_settings(&self, value: &serde_json::Value) {
+		if let Some(store) = self.global_settings.lock().await.as_ref() {
+			store.receive(value.clone());
+		}
+	}
+
+	/// Cache global settings sent by the OpenAction server and resolve pending [`GlobalSettings::load`] calls
+	pub(crate) async fn handle_did_receive_global_settings(&self, value: &serde_json::Value) {
+		self.receive_global_settings(value).await;
+		for sender in std::mem::take(&mut *self.pending_global_settings.lock().await) {
+			let _ = sender.send(value.clone());
+		}
+	}
+}
+
+impl<T> GlobalSettings<T>
+where
+	T: Serialize + DeserializeOwned + Default + Clone + Send + Sync + 'static,
+{
+	fn plugin(&self) -> Result<Plugin> {
+		let runtime = self.0.runtime.upgrade().ok_or(OpenActionError::NotConnected)?;
+		Ok(Plugin { runtime })
+	}
+
+	/// The most recently received or set global settings, or `None` if they have not been loaded yet
+	pub fn current(&self) -> Option<Arc<T>> {
+		self.0.sender.borrow().clone()
+	}
+
+	/// Subscribe to changes to the global settings
+	pub fn watch(&self) -> watch::Receiver<Option<Arc<T>>> {
+		self.0.sender.subscribe()
+	}
+
+	/// Request the global settings and wait for the OpenAction server to respond with them
+	///
+	/// Global events are not processed while a global event handler is running (or, unless
+	/// [`DispatchMode::Concurrent`](crate::DispatchMode::Concurrent) is used, while any event handler is running), so
+	/// this should be awaited from a spawned task rather than directly inside an event handler.
+	pub async fn load(&self, timeout: Duration) -> Result<Arc<T>> {
+		let plugin = self.plugin()?;
+		let (sender, receiver) = oneshot::channel();
+		plugin.runtime.pending_global_settings.lock().await.push(sender);
+
+		let result = match plugin.get_global_settings().await {
+			Ok(()) => tokio::time::timeout(timeout, receiver)
+				.await
+				.ok()
+				.and_then(|value| value.ok())
+				.ok_or(OpenActionError::ResponseTimeout("didReceiveGlobalSettings")),
+			Err(error) => {
+				drop(receiver);
+				Err(error)
+			}
+		};
+		if result.is_err() {
+			plugin
+				.runtime
+				.pending_global_settings
+				.lock()
+				.await
+				.retain(|sender| !sender.is_closed());
+		}
+
+		Ok(Arc::new(serde_json::from_value(result?)?))
+	}
+
+	/// Modify the global settings and persist them, returning the new value
+	///
+	/// Concurrent calls are applied one after another, so no update is lost. If the global settings have not been
+	/// loaded yet, they are [loaded](Self::load) first, waiting at most five seconds, so the same caveats apply.
+	pub async fn update(&self, f: impl FnOnce(&mut T)) -> Result<Arc<T>> {
+		let _guard = self.0.update.lock().await;
+		let current = match self.current() {
+			Some(current) => current,
+			None => self.load(LOAD_TIMEOUT).await?,
+		};
+		let mut settings = (*current).clone();
+		f(&mut settings);
+		self.plugin()?.set_global_settings(&settings).await?;
+		Ok(self.current().unwrap_or_else(|| Arc::new(settings)))
+	}
+}
@@ -0,0 +1,37 @@
+use super::Runtime;
+
+use std::sync::Arc;
+
+/// A handle for stopping the plugin event loop started by [`run`](crate::run)
+#[derive(Clone)]
+pub struct PluginHandle {
+	pub(super) runtime: Arc<Runtime>,
+}
+
+impl PluginHandle {
+	/// Request a graceful shutdown
+	///
+	/// [`Action::will_disappear`](crate::Action::will_disappear) is called for every remaining instance, pending
+	/// outbound events are written and the WebSocket is closed before [`run`](crate::run) returns.
+	pub fn shutdown(&self) {
+		self.runtime.shutdown.send_replace(true);
+	}
+
+	/// Whether a shutdown has been requested and not yet completed
+	pub fn is_shutting_down(&self) -> bool {
+		*self.runtime.shutdown.borrow()
+	}
+}
+
+/// Get a handle for stopping the default plugin
+pub fn plugin_handle() -> PluginHandle {
+	super::default_plugin().handle()
+}
+
+impl Runtime {
+	/// Wait until a shutdown is requested
+	pub(crate) async fn shutdown_requested(&self) {
+		let mut receiver = self.shutdown.subscribe();
+		let _ = receiver.wait_for(|&shutdown| shutdown).await;
+	}
+}
@@ -1,121 +1,348 @@
-use super::resolve;
+use super::wrapper::ErasedAction;
+use super::{Instance, Runtime};
 
-use crate::OpenActionResult as Result;
 use crate::inbound::{
-	DialPressEvent, DialRotateEvent, DidReceiveSettingsEvent, KeyEvent, PropertyInspectorAppearEvent,
-	SendToPluginEvent, TitleParametersDidChangeEvent, TouchTapEvent,
+	Controller, DialPressEvent, DialRotateEvent, DidReceiveSettingsEvent, Event, KeyEvent,
+	PropertyInspectorAppearEvent, SendToPluginEvent, TitleParametersDidChangeEvent, TouchTapEvent,
 };
+use crate::{OpenActionError, OpenActionResult as Result};
 
+use std::future::Future;
 use std::sync::atomic::Ordering::Relaxed;
 
-async fn update_instance(instance: &super::instance::Instance, state: u16, settings: &serde_json::Value) {
+/// Update the state and settings of an instance, returning the previous settings if they changed
+async fn update_instance(instance: &Instance, state: u16, settings: &serde_json::Value) -> Option<serde_json::Value> {
 	instance.current_state_index.store(state, Relaxed);
-	*instance.settings_json.write().await = settings.clone();
+	instance.store_settings_json(settings).await
 }
 
-pub(crate) async fn handle_key_down(event: KeyEvent) -> Result<()> {
-	if let Some((action, instance)) = resolve(&event.action, &event.context).await? {
-		update_instance(&instance, event.payload.state, &event.payload.settings).await;
-		action.call_key_down(&instance, event.payload).await?;
+/// Whether an event only sent for the given controller fits both the instance and its action, warning if not
+fn accepts(action: &dyn ErasedAction, instance: &Instance, controller: Controller, event: &str) -> bool {
+	let fits_instance = matches!(instance.controller, Controller::Unknown(_)) || instance.controller == controller;
+	if fits_instance && action.controllers().contains(&controller) {
+		return true;
 	}
-	Ok(())
+	log::warn!(
+		"Rejected {} event for instance '{}' of action '{}' bound to the {} controller",
+		event,
+		instance.instance_id,
+		instance.action_uuid,
+		instance.controller
+	);
+	false
 }
 
-pub(crate) async fn handle_key_up(event: KeyEvent) -> Result<()> {
-	if let Some((action, instance)) = resolve(&event.action, &event.context).await? {
-		update_instance(&instance, event.payload.state, &event.payload.settings).await;
-		action.call_key_up(&instance, event.payload).await?;
+impl Runtime {
+	/// Call the settings changed handler of an instance if an inbound event changed its settings
+	pub(super) async fn settings_changed(
+		&self,
+		action: &dyn ErasedAction,
+		instance: &Instance,
+		old: Option<serde_json::Value>,
+	) {
+		if let Some(old) = old
+			&& let Err(error) = self
+				.invoke(
+					action,
+					instance,
+					"settingsChanged",
+					action.call_settings_changed(instance, old),
+				)
+				.await
+		{
+			log::error!("Failed to process settings change: {}", error);
+		}
+	}
+
+	/// Run an event handler of an instance within the action's timeout, attaching the action UUID and instance ID to
+	/// any error it returns and handling the error according to the action's [`ErrorPolicy`](super::ErrorPolicy)
+	pub(super) async fn invoke(
+		&self,
+		action: &dyn ErasedAction,
+		instance: &Instance,
+		event: &'static str,
+		handler: impl Future<Output = Result<()>>,
+	) -> Result<()> {
+		let timeout = match action.handler_timeout() {
+			Some(timeout) => Some(timeout),
+			None => *self.handler_timeout.lock().await,
+		};
+		let Err(error) = super::with_timeout(timeout, super::catch_panic(handler)).await else {
+			return Ok(());
+		};
+		let error = OpenActionError::Instance {
+			action: instance.action_uuid.clone(),
+			instance: instance.instance_id.clone(),
+			event,
+			source: Box::new(error),
+		};
+
+		let policy = match action.error_policy() {
+			Some(policy) => policy,
+			None => *self.error_policy.lock().await,
+		};
+		if policy.show_alert
+			&& let Err(error) = instance.show_alert().await
+		{
+			log::warn!("Failed to show alert for failed event handler: {}", error);
+		}
+		if policy.log_message {
+			let message = error.to_string();
+			if let Err(error) = self.with_outbound(move |mgr| mgr.log_message(message)).await {
+				log::warn!("Failed to forward event handler error to server log: {}", error);
+			}
+		}
+		if let Err(error) = super::catch_panic(action.call_on_error(instance, &error)).await {
+			log::error!(
+				"Failed to run error handler of action '{}': {}",
+				instance.action_uuid,
+				error
+			);
+		}
+
+		Err(error)
+	}
+
+	pub(crate) async fn handle_key_down(&self, event: KeyEvent) -> Result<()> {
+		let Some(instance) = self.get_instance(&event.context) else {
+			return Ok(());
+		};
+		let old_settings = update_instance(&instance, event.payload.state, &event.payload.settings).await;
+		*instance.user_desired_state.lock().unwrap() = event.payload.user_desired_state;
+		self.events.publish(|| Event::KeyDown(instance.clone(), event.clone()));
+		let Some(action) = self.get_action(&event.action).await else {
+			return Ok(());
+		};
+		self.settings_changed(&*action, &instance, old_settings).await;
+		if !accepts(&*action, &instance, Controller::Keypad, "keyDown") {
+			return Ok(());
+		}
+		self.invoke(&*action, &instance, "keyDown", action.call_key_down(&instance))
+			.await
 	}
-	Ok(())
-}
 
-pub(crate) async fn handle_dial_rotate(event: DialRotateEvent) -> Result<()> {
-	if let Some((action, instance)) = resolve(&event.action, &event.context).await? {
-		update_instance(
+	pub(crate) async fn handle_key_up(&self, event: KeyEvent) -> Result<()> {
+		let Some(instance) = self.get_instance(&event.context) else {
+			return Ok(());
+		};
+		let old_settings = update_instance(&instance, event.payload.state, &event.payload.settings).await;
+		*instance.user_desired_state.lock().unwrap() = event.payload.user_desired_state;
+		self.events.publish(|| Event::KeyUp(instance.clone(), event.clone()));
+		let Some(action) = self.get_action(&event.action).await else {
+			return Ok(());
+		};
+		self.settings_changed(&*action, &instance, old_settings).await;
+		if !accepts(&*action, &instance, Controller::Keypad, "keyUp") {
+			return Ok(());
+		}
+		self.invoke(&*action, &instance, "keyUp", action.call_key_up(&instance))
+			.await
+	}
+
+	pub(crate) async fn handle_dial_rotate(&self, event: DialRotateEvent) -> Result<()> {
+		let Some(instance) = self.get_instance(&event.context) else {
+			return Ok(());
+		};
+		let old_settings = update_instance(
 			&instance,
 			instance.current_state_index.load(Relaxed),
 			&event.payload.settings,
 		)
 		.await;
-		action.call_dial_rotate(&instance, event.payload).await?;
+		self.events
+			.publish(|| Event::DialRotate(instance.clone(), event.clone()));
+		let Some(action) = self.get_action(&event.action).await else {
+			return Ok(());
+		};
+		self.settings_changed(&*action, &instance, old_settings).await;
+		if !accepts(&*action, &instance, Controller::Encoder, "dialRotate") {
+			return Ok(());
+		}
+		self.invoke(
+			&*action,
+			&instance,
+			"dialRotate",
+			action.call_dial_rotate(&instance, event.payload),
+		)
+		.await
 	}
-	Ok(())
-}
 
-pub(crate) async fn handle_dial_down(event: DialPressEvent) -> Result<()> {
-	if let Some((action, instance)) = resolve(&event.action, &event.context).await? {
-		update_instance(
+	pub(crate) async fn handle_dial_down(&self, event: DialPressEvent) -> Result<()> {
+		let Some(instance) = self.get_instance(&event.context) else {
+			return Ok(());
+		};
+		let old_settings = update_instance(
 			&instance,
 			instance.current_state_index.load(Relaxed),
 			&event.payload.settings,
 		)
 		.await;
-		action.call_dial_down(&instance, event.payload).await?;
+		self.events.publish(|| Event::DialDown(instance.clone(), event.clone()));
+		let Some(action) = self.get_action(&event.action).await else {
+			return Ok(());
+		};
+		self.settings_changed(&*action, &instance, old_settings).await;
+		if !accepts(&*action, &instance, Controller::Encoder, "dialDown") {
+			return Ok(());
+		}
+		self.invoke(&*action, &instance, "dialDown", action.call_dial_down(&instance))
+			.await
 	}
-	Ok(())
-}
 
-pub(crate) async fn handle_dial_up(event: DialPressEvent) -> Result<()> {
-	if let Some((action, instance)) = resolve(&event.action, &event.context).await? {
-		update_instance(
+	pub(crate) async fn handle_dial_up(&self, event: DialPressEvent) -> Result<()> {
+		let Some(instance) = self.get_instance(&event.context) else {
+			return Ok(());
+		};
+		let old_settings = update_instance(
 			&instance,
 			instance.current_state_index.load(Relaxed),
 			&event.payload.settings,
 		)
 		.await;
-		action.call_dial_up(&instance, event.payload).await?;
+		self.events.publish(|| Event::DialUp(instance.clone(), event.clone()));
+		let Some(action) = self.get_action(&event.action).await else {
+			return Ok(());
+		};
+		self.settings_changed(&*action, &instance, old_settings).await;
+		if !accepts(&*action, &instance, Controller::Encoder, "dialUp") {
+			return Ok(());
+		}
+		self.invoke(&*action, &instance, "dialUp", action.call_dial_up(&instance))
+			.await
 	}
-	Ok(())
-}
 
-pub(crate) async fn handle_touch_tap(event: TouchTapEvent) -> Result<()> {
-	if let Some((action, instance)) = resolve(&event.action, &event.context).await? {
-		update_instance(
+	pub(crate) async fn handle_touch_tap(&self, event: TouchTapEvent) -> Result<()> {
+		let Some(instance) = self.get_instance(&event.context) else {
+			return Ok(());
+		};
+		let old_settings = update_instance(
 			&instance,
 			instance.current_state_index.load(Relaxed),
 			&event.payload.settings,
 		)
 		.await;
-		action.call_touch_tap(&instance, event.payload).await?;
+		self.events.publish(|| Event::TouchTap(instance.clone(), event.clone()));
+		let Some(action) = self.get_action(&event.action).await else {
+			return Ok(());
+		};
+		self.settings_changed(&*action, &instance, old_settings).await;
+		if !accepts(&*action, &instance, Controller::Encoder, "touchTap") {
+			return Ok(());
+		}
+		self.invoke(
+			&*action,
+			&instance,
+			"touchTap",
+			action.call_touch_tap(&instance, event.payload),
+		)
+		.await
 	}
-	Ok(())
-}
 
-pub(crate) async fn handle_did_receive_settings(event: DidReceiveSettingsEvent) -> Result<()> {
-	if let Some((action, instance)) = resolve(&event.action, &event.context).await? {
-		update_instance(&instance, event.payload.state, &event.payload.settings).await;
-		action.call_did_receive_settings(&instance, event.payload).await?;
+	pub(crate) async fn handle_did_receive_settings(&self, event: DidReceiveSettingsEvent) -> Result<()> {
+		let Some(instance) = self.get_instance(&event.context) else {
+			return Ok(());
+		};
+		instance.current_state_index.store(event.payload.state, Relaxed);
+		let old_settings = instance.store_received_settings_json(&event.payload.settings).await;
+		if let Some((_, pending)) = self.pending_settings.remove(&event.context) {
+			for sender in pending {
+				let _ = sender.send(());
+			}
+		}
+		self.events
+			.publish(|| Event::DidReceiveSettings(instance.clone(), event.clone()));
+		let Some(action) = self.get_action(&event.action).await else {
+			return Ok(());
+		};
+		self.settings_changed(&*action, &instance, old_settings).await;
+		self.invoke(
+			&*action,
+			&instance,
+			"didReceiveSettings",
+			action.call_did_receive_settings(&instance),
+		)
+		.await
 	}
-	Ok(())
-}
 
-pub(crate) async fn handle_title_parameters_did_change(event: TitleParametersDidChangeEvent) -> Result<()> {
-	if let Some((action, instance)) = resolve(&event.action, &event.context).await? {
-		update_instance(&instance, event.payload.state, &event.payload.settings).await;
-		action
-			.call_title_parameters_did_change(&instance, event.payload)
-			.await?;
+	pub(crate) async fn handle_title_parameters_did_change(&self, event: TitleParametersDidChangeEvent) -> Result<()> {
+		let Some(instance) = self.get_instance(&event.context) else {
+			return Ok(());
+		};
+		let old_settings = update_instance(&instance, event.payload.state, &event.payload.settings).await;
+		self.events
+			.publish(|| Event::TitleParametersDidChange(instance.clone(), event.clone()));
+		let Some(action) = self.get_action(&event.action).await else {
+			return Ok(());
+		};
+		self.settings_changed(&*action, &instance, old_settings).await;
+		self.invoke(
+			&*action,
+			&instance,
+			"titleParametersDidChange",
+			action.call_title_parameters_did_change(&instance, event.payload),
+		)
+		.await
 	}
-	Ok(())
-}
 
-pub(crate) async fn handle_property_inspector_did_appear(event: PropertyInspectorAppearEvent) -> Result<()> {
-	if let Some((action, instance)) = resolve(&event.action, &event.context).await? {
-		action.call_pi_did_appear(&instance).await?;
+	pub(crate) async fn handle_property_inspector_did_appear(&self, event: PropertyInspectorAppearEvent) -> Result<()> {
+		let Some(instance) = self.get_instance(&event.context) else {
+			return Ok(());
+		};
+		self.events
+			.publish(|| Event::PropertyInspectorDidAppear(instance.clone(), event.clone()));
+		let Some(action) = self.get_action(&event.action).await else {
+			return Ok(());
+		};
+		self.invoke(
+			&*action,
+			&instance,
+			"propertyInspectorDidAppear",
+			action.call_pi_did_appear(&instance),
+		)
+		.await
 	}
-	Ok(())
-}
 
-pub(crate) async fn handle_property_inspector_did_disappear(event: PropertyInspectorAppearEvent) -> Result<()> {
-	if let Some((action, instance)) = resolve(&event.action, &event.context).await? {
-		action.call_pi_did_disappear(&instance).await?;
+	pub(crate) async fn handle_property_inspector_did_disappear(
+		&self,
+		event: PropertyInspectorAppearEvent,
+	) -> Result<()> {
+		let Some(instance) = self.get_instance(&event.context) else {
+			return Ok(());
+		};
+		self.events
+			.publish(|| Event::PropertyInspectorDidDisappear(instance.clone(), event.clone()));
+		let Some(action) = self.get_action(&event.action).await else {
+			return Ok(());
+		};
+		self.invoke(
+			&*action,
+			&instance,
+			"propertyInspectorDidDisappear",
+			action.call_pi_did_disappear(&instance),
+		)
+		.await
 	}
-	Ok(())
-}
 
-pub(crate) async fn handle_send_to_plugin(event: SendToPluginEvent) -> Result<()> {
-	if let Some((action, instance)) = resolve(&event.action, &event.context).await? {
-		action.call_send_to_plugin(&instance, event.payload).await?;
+	pub(crate) async fn handle_send_to_plugin(&self, event: SendToPluginEvent) -> Result<()> {
+		let Some(instance) = self.get_instance(&event.context) else {
+			return Ok(());
+		};
+		self.events
+			.publish(|| Event::SendToPlugin(instance.clone(), event.clone()));
+		let Some(action) = self.get_action(&event.action).await else {
+			return Ok(());
+		};
+		let handler = self
+			.property_inspectors
+			.get(action.uuid())
+			.map(|handler| handler.clone());
+		let handlers = async {
+			action.call_send_to_plugin(&instance, event.payload.clone()).await?;
+			match handler {
+				Some(handler) => handler.call(&instance, event.payload).await,
+				None => Ok(()),
+			}
+		};
+		self.invoke(&*action, &instance, "sendToPlugin", handlers).await
 	}
-	Ok(())
 }
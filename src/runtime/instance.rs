@@ -1,11 +1,100 @@
-use super::InstanceId;
+use super::{Action, InstanceId, Runtime};
 
-use crate::OpenActionResult as Result;
-use crate::inbound::Coordinates;
-use crate::outbound::OutboundEventManager;
+use crate::TriggerDescription;
+use crate::inbound::{Controller, Coordinates};
+use crate::{OpenActionError, OpenActionResult as Result};
 
+use std::any::Any;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use serde::Deserialize;
 use serde_json::Value;
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, oneshot, watch};
+
+/// Field of the settings JSON holding the number of migrations applied to it
+const SETTINGS_VERSION_KEY: &str = "_settingsVersion";
+
+/// Apply the action's pending settings migrations, returning whether the settings were changed
+///
+/// Empty settings, as given to a newly created instance, are only stamped with the latest version.
+pub(crate) fn migrate_settings<A: Action>(json: &mut Value) -> bool {
+	let latest = A::SETTINGS_MIGRATIONS.len();
+	let Some(object) = json.as_object() else {
+		return false;
+	};
+	if latest == 0 {
+		return false;
+	}
+	if object.is_empty() {
+		stamp_settings_version::<A>(json);
+		return true;
+	}
+	let version = object.get(SETTINGS_VERSION_KEY).and_then(Value::as_u64).unwrap_or(0) as usize;
+	if version >= latest {
+		if version > latest {
+			log::warn!(
+				"Settings for action {} are from a newer version ({} > {})",
+				A::UUID,
+				version,
+				latest
+			);
+		}
+		return false;
+	}
+
+	for migration in &A::SETTINGS_MIGRATIONS[version..] {
+		*json = migration(std::mem::take(json));
+	}
+	stamp_settings_version::<A>(json);
+	true
+}
+
+/// Keep the version of the current settings if new settings without one are received, such as settings set by a
+/// property inspector, returning whether it was added
+fn restore_settings_version(current: &Value, new: &mut Value) -> bool {
+	if let Some(version) = current.get(SETTINGS_VERSION_KEY)
+		&& let Some(object) = new.as_object_mut()
+		&& !object.is_empty()
+		&& !object.contains_key(SETTINGS_VERSION_KEY)
+	{
+		object.insert(SETTINGS_VERSION_KEY.to_owned(), version.clone());
+		return true;
+	}
+	false
+}
+
+/// Deserialize settings as the settings type of an action, ignoring the settings version
+pub(crate) fn deserialize_settings<A: Action>(json: &Value) -> serde_json::Result<A::Settings> {
+	match json.as_object() {
+		Some(object) if object.contains_key(SETTINGS_VERSION_KEY) => {
+			let mut object = object.clone();
+			object.remove(SETTINGS_VERSION_KEY);
+			A::Settings::deserialize(Value::Object(object))
+		}
+		_ => A::Settings::deserialize(json),
+	}
+}
+
+/// Record that the settings are up to date with the action's migrations
+fn stamp_settings_version<A: Action>(json: &mut Value) {
+	if !A::SETTINGS_MIGRATIONS.is_empty()
+		&& let Some(object) = json.as_object_mut()
+	{
+		object.insert(SETTINGS_VERSION_KEY.to_owned(), A::SETTINGS_MIGRATIONS.len().into());
+	}
+}
+
+/// Settings that could not be deserialized as the settings type of an action
+pub(crate) struct InvalidSettings {
+	pub(crate) error: serde_json::Error,
+	/// Whether the property inspector has been told about the error
+	pub(crate) notified: AtomicBool,
+}
+
+/// The settings of an instance deserialized as the settings type of an action, or why that failed
+pub(crate) type TypedSettings<A> = std::result::Result<Arc<<A as Action>::Settings>, Arc<InvalidSettings>>;
 
 /// An instance of an action bound to the device surface
 pub struct Instance {
@@ -16,105 +105,376 @@ pub struct Instance {
 	/// The device this instance is bound to
 	pub device_id: String,
 	/// The controller this instance is bound to
-	pub controller: String,
+	pub controller: Controller,
 	/// Coordinates on the device surface where this instance is bound
 	pub coordinates: Option<Coordinates>,
 	/// Whether or not this instance is part of a Multi Action
 	pub is_in_multi_action: bool,
 	/// Index of the currently active state within the states defined in the plugin manifest
 	pub current_state_index: std::sync::atomic::AtomicU16,
+	pub(crate) user_desired_state: Mutex<Option<u16>>,
 	pub(crate) settings_json: RwLock<Value>,
+	/// The settings deserialized from `settings_json`, cleared whenever it changes
+	pub(crate) typed_settings: Mutex<Option<Arc<dyn Any + Send + Sync>>>,
+	pub(crate) settings_watch: watch::Sender<Value>,
+	pub(crate) runtime: Weak<Runtime>,
 }
 
 impl Instance {
-	async fn outbound(&self) -> tokio::sync::MutexGuard<'_, Option<OutboundEventManager>> {
-		super::RUNTIME.outbound.lock().await
+	/// The runtime of the plugin this instance belongs to, if it is still running
+	fn runtime(&self) -> Result<Arc<Runtime>> {
+		self.runtime.upgrade().ok_or(OpenActionError::NotConnected)
+	}
+
+	/// Replace the settings of this instance, invalidating the cached typed settings and returning the previous
+	/// settings if they changed
+	///
+	/// Settings without a version are stored as they are and migrated again, as they may predate a migration that has
+	/// not reached the OpenAction server yet.
+	pub(crate) async fn store_settings_json(&self, value: &Value) -> Option<Value> {
+		let mut json = self.settings_json.write().await;
+		self.replace_settings_json(&mut json, value.clone())
+	}
+
+	/// Replace the settings of this instance with settings received with `didReceiveSettings`, keeping the current
+	/// settings version if they do not have one
+	///
+	/// These settings were set by the property inspector, which does not know about the settings version.
+	pub(crate) async fn store_received_settings_json(&self, value: &Value) -> Option<Value> {
+		let mut json = self.settings_json.write().await;
+		let mut value = value.clone();
+		if restore_settings_version(&json, &mut value) && *json != value {
+			self.write_back_settings(&value).await;
+		}
+		self.replace_settings_json(&mut json, value)
+	}
+
+	fn replace_settings_json(&self, json: &mut Value, value: Value) -> Option<Value> {
+		if *json == value {
+			return None;
+		}
+		let old = std::mem::replace(json, value.clone());
+		self.typed_settings.lock().unwrap().take();
+		self.settings_watch.send_replace(value);
+		Some(old)
+	}
+
+	/// Write settings that have just been migrated back to the OpenAction server
+	async fn persist_migrated_settings(&self, json: &Value) {
+		self.settings_watch.send_replace(json.clone());
+		self.write_back_settings(json).await;
+	}
+
+	/// Write settings changed by the runtime rather than the user back to the OpenAction server
+	async fn write_back_settings(&self, json: &Value) {
+		let context = self.instance_id.clone();
+		let value = json.clone();
+		let result = match self.runtime() {
+			Ok(runtime) => runtime.with_outbound(move |mgr| mgr.set_settings(context, value)).await,
+			Err(error) => Err(error),
+		};
+		if let Err(error) = result {
+			log::warn!(
+				"Failed to write back settings of instance '{}': {}",
+				self.instance_id,
+				error
+			);
+		}
+	}
+
+	/// The state the user selected for this instance within a Multi Action, as sent with the latest key event
+	pub fn user_desired_state(&self) -> Option<u16> {
+		*self.user_desired_state.lock().unwrap()
+	}
+
+	/// Subscribe to changes to the settings of this instance, whether received from the OpenAction server or set by
+	/// the plugin
+	pub fn watch_settings(&self) -> watch::Receiver<Value> {
+		self.settings_watch.subscribe()
+	}
+
+	/// The settings of this instance as the settings type of its action, only deserialized again after they change
+	///
+	/// A failure to deserialize them is logged and cached as well, until the settings change.
+	pub(crate) async fn typed_settings<A: Action>(&self) -> TypedSettings<A> {
+		if let Some(settings) = self.typed_settings.lock().unwrap().clone()
+			&& let Ok(settings) = settings.downcast::<TypedSettings<A>>()
+		{
+			return (*settings).clone();
+		}
+
+		// Hold the lock so the settings cannot change between deserializing and caching them
+		let mut json = self.settings_json.write().await;
+		if migrate_settings::<A>(&mut json) {
+			self.persist_migrated_settings(&json).await;
+		}
+		let settings = deserialize_settings::<A>(&json).map(Arc::new).map_err(|error| {
+			log::error!("Failed to deserialize settings for action {}: {}", A::UUID, error);
+			Arc::new(InvalidSettings {
+				error,
+				notified: AtomicBool::new(false),
+			})
+		});
+		*self.typed_settings.lock().unwrap() = Some(Arc::new(settings.clone()));
+		settings
+	}
+
+	/// Use the given settings in place of invalid settings until the settings change, unless they already have
+	pub(crate) fn replace_invalid_settings<A: Action>(
+		&self,
+		invalid: &Arc<InvalidSettings>,
+		settings: Arc<A::Settings>,
+	) {
+		let mut cache = self.typed_settings.lock().unwrap();
+		if let Some(cached) = cache.as_ref()
+			&& let Some(Err(cached)) = cached.downcast_ref::<TypedSettings<A>>()
+			&& Arc::ptr_eq(cached, invalid)
+		{
+			*cache = Some(Arc::new(TypedSettings::<A>::Ok(settings)));
+		}
 	}
 
 	/// <https://openaction.amankhanna.me/5_serverbound/states.html#settitle>
 	pub async fn set_title(&self, title: Option<impl Into<String>>, state: Option<u16>) -> Result<()> {
-		if let Some(mgr) = self.outbound().await.as_mut() {
-			mgr.set_title(self.instance_id.clone(), title.map(Into::into), state)
-				.await?;
-		}
-		Ok(())
+		let (context, title) = (self.instance_id.clone(), title.map(Into::into));
+		self.runtime()?
+			.with_outbound(move |mgr| mgr.set_title(context, title, state))
+			.await
 	}
 
 	/// <https://openaction.amankhanna.me/5_serverbound/states.html#setimage>
 	pub async fn set_image(&self, image: Option<impl Into<String>>, state: Option<u16>) -> Result<()> {
-		if let Some(mgr) = self.outbound().await.as_mut() {
-			mgr.set_image(self.instance_id.clone(), image.map(Into::into), state)
-				.await?;
-		}
-		Ok(())
+		let (context, image) = (self.instance_id.clone(), image.map(Into::into));
+		self.runtime()?
+			.with_outbound(move |mgr| mgr.set_image(context, image, state))
+			.await
 	}
 
 	/// <https://openaction.amankhanna.me/5_serverbound/states.html#setstate>
 	pub async fn set_state(&self, state: u16) -> Result<()> {
-		if let Some(mgr) = self.outbound().await.as_mut() {
-			mgr.set_state(self.instance_id.clone(), state).await?;
-		}
-		Ok(())
+		let context = self.instance_id.clone();
+		self.runtime()?
+			.with_outbound(move |mgr| mgr.set_state(context, state))
+			.await
 	}
 
 	/// <https://openaction.amankhanna.me/5_serverbound/states.html#setfeedback>
 	pub async fn set_feedback(&self, feedback: &impl serde::Serialize) -> Result<()> {
-		if let Some(mgr) = self.outbound().await.as_mut() {
-			mgr.set_feedback(self.instance_id.clone(), feedback).await?;
-		}
-		Ok(())
+		let (context, feedback) = (self.instance_id.clone(), serde_json::to_value(feedback)?);
+		self.runtime()?
+			.with_outbound(move |mgr| mgr.set_feedback(context, &feedback))
+			.await
 	}
 
 	/// <https://openaction.amankhanna.me/5_serverbound/states.html#setfeedbacklayout>
 	pub async fn set_feedback_layout(&self, layout: String) -> Result<()> {
-		if let Some(mgr) = self.outbound().await.as_mut() {
-			mgr.set_feedback_layout(self.instance_id.clone(), layout).await?;
-		}
-		Ok(())
+		let context = self.instance_id.clone();
+		self.runtime()?
+			.with_outbound(move |mgr| mgr.set_feedback_layout(context, layout))
+			.await
+	}
+
+	/// Set the descriptions of this encoder's interactions shown on the device
+	pub async fn set_trigger_description(&self, description: TriggerDescription) -> Result<()> {
+		let context = self.instance_id.clone();
+		self.runtime()?
+			.with_outbound(move |mgr| mgr.set_trigger_description(context, description))
+			.await
 	}
 
 	/// <https://openaction.amankhanna.me/5_serverbound/misc.html#showalert>
 	pub async fn show_alert(&self) -> Result<()> {
-		if let Some(mgr) = self.outbound().await.as_mut() {
-			mgr.show_alert(self.instance_id.clone()).await?;
-		}
-		Ok(())
+		let context = self.instance_id.clone();
+		self.runtime()?.with_outbound(move |mgr| mgr.show_alert(context)).await
 	}
 
 	/// <https://openaction.amankhanna.me/5_serverbound/misc.html#showok>
 	pub async fn show_ok(&self) -> Result<()> {
-		if let Some(mgr) = self.outbound().await.as_mut() {
-			mgr.show_ok(self.instance_id.clone()).await?;
-		}
-		Ok(())
+		let context = self.instance_id.clone();
+		self.runtime()?.with_outbound(move |mgr| mgr.show_ok(context)).await
 	}
 
 	/// <https://openaction.amankhanna.me/5_serverbound/settings.html#setsettings>
+	///
+	/// The settings version recorded by [`Action::SETTINGS_MIGRATIONS`] is kept if `value` does not set one.
 	pub async fn set_settings(&self, value: &impl serde::Serialize) -> Result<()> {
-		let value = serde_json::to_value(value)?;
-		if let Some(rec) = super::RUNTIME.instances.get(&self.instance_id) {
-			*rec.settings_json.write().await = value.clone();
-			if let Some(mgr) = self.outbound().await.as_mut() {
-				mgr.set_settings(self.instance_id.clone(), value).await?;
-			}
+		let mut value = serde_json::to_value(value)?;
+		if let Some(object) = value.as_object_mut()
+			&& !object.contains_key(SETTINGS_VERSION_KEY)
+			&& let Some(version) = self.settings_json.read().await.get(SETTINGS_VERSION_KEY)
+		{
+			object.insert(SETTINGS_VERSION_KEY.to_owned(), version.clone());
+		}
+		let runtime = self.runtime()?;
+		if let Some(rec) = runtime.instances.get(&self.instance_id).map(|rec| rec.clone()) {
+			let context = self.instance_id.clone();
+			let sent = value.clone();
+			runtime
+				.with_outbound(move |mgr| mgr.set_settings(context, sent))
+				.await?;
+			rec.store_settings_json(&value).await;
 		}
 		Ok(())
 	}
 
+	/// Modify the settings of this instance and persist them, returning the new value
+	///
+	/// The closure is applied to the latest settings while no other update or inbound event can change them, so
+	/// concurrent updates are applied one after another and none are lost. Fails without modifying the settings if
+	/// they cannot be deserialized as the settings type of the action.
+	pub async fn update_settings<A: Action>(&self, f: impl FnOnce(&mut A::Settings)) -> Result<Arc<A::Settings>> {
+		let runtime = self.runtime()?;
+		let mut json = self.settings_json.write().await;
+		if migrate_settings::<A>(&mut json) {
+			self.persist_migrated_settings(&json).await;
+		}
+		let mut settings = deserialize_settings::<A>(&json)?;
+		f(&mut settings);
+		let mut value = serde_json::to_value(&settings)?;
+		stamp_settings_version::<A>(&mut value);
+
+		let context = self.instance_id.clone();
+		let sent = value.clone();
+		runtime
+			.with_outbound(move |mgr| mgr.set_settings(context, sent))
+			.await?;
+		*json = value.clone();
+		self.settings_watch.send_replace(value);
+		let settings = Arc::new(settings);
+		*self.typed_settings.lock().unwrap() = Some(Arc::new(TypedSettings::<A>::Ok(settings.clone())));
+		Ok(settings)
+	}
+
 	/// <https://openaction.amankhanna.me/5_serverbound/settings.html#getsettings>
 	pub async fn get_settings(&self) -> Result<()> {
-		if let Some(mgr) = self.outbound().await.as_mut() {
-			mgr.get_settings(self.instance_id.clone()).await?;
+		let context = self.instance_id.clone();
+		self.runtime()?
+			.with_outbound(move |mgr| mgr.get_settings(context))
+			.await
+	}
+
+	/// Request the settings of this instance and wait for the OpenAction server to respond with them
+	///
+	/// Inbound events for an instance are not processed while one of its event handlers is running (or, unless
+	/// [`DispatchMode::Concurrent`](crate::DispatchMode::Concurrent) is used, while any event handler is running), so
+	/// this should be awaited from a spawned task rather than directly inside an event handler.
+	pub async fn fetch_settings<A: Action>(&self, timeout: Duration) -> Result<A::Settings> {
+		let runtime = self.runtime()?;
+		let (sender, receiver) = oneshot::channel();
+		runtime
+			.pending_settings
+			.entry(self.instance_id.clone())
+			.or_default()
+			.push(sender);
+
+		let result = match self.get_settings().await {
+			Ok(()) => tokio::time::timeout(timeout, receiver)
+				.await
+				.ok()
+				.and_then(|value| value.ok())
+				.ok_or(OpenActionError::ResponseTimeout("didReceiveSettings")),
+			Err(error) => {
+				drop(receiver);
+				Err(error)
+			}
+		};
+		if result.is_err() {
+			runtime.pending_settings.remove_if_mut(&self.instance_id, |_, pending| {
+				pending.retain(|sender| !sender.is_closed());
+				pending.is_empty()
+			});
 		}
-		Ok(())
+
+		result?;
+		// The response has already been stored, so migrate, persist and cache it like any other settings
+		let _ = self.typed_settings::<A>().await;
+		Ok(deserialize_settings::<A>(&*self.settings_json.read().await)?)
 	}
 
 	/// <https://openaction.amankhanna.me/5_serverbound/property_inspector.html#sendtopropertyinspector>
 	pub async fn send_to_property_inspector(&self, value: impl serde::Serialize) -> Result<()> {
 		let value = serde_json::to_value(value)?;
-		if let Some(mgr) = self.outbound().await.as_mut() {
-			mgr.send_to_property_inspector(self.instance_id.clone(), value).await?;
+		let context = self.instance_id.clone();
+		self.runtime()?
+			.with_outbound(move |mgr| mgr.send_to_property_inspector(context, value))
+			.await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use serde_json::json;
+
+	#[derive(serde::Serialize, serde::Deserialize, Default)]
+	struct CounterSettings {
+		count: u32,
+	}
+
+	struct CounterAction;
+
+	impl Action for CounterAction {
+		const UUID: &'static str = "com.example.counter";
+		type Settings = CounterSettings;
+		const SETTINGS_MIGRATIONS: &'static [fn(Value) -> Value] = &[
+			|json| json!({ "value": json["value"].as_u64().unwrap_or(0) * 2 }),
+			|json| json!({ "count": json["value"] }),
+		];
+	}
+
+	#[test]
+	fn migrates_unversioned_settings() {
+		let mut json = json!({ "value": 3 });
+		assert!(migrate_settings::<CounterAction>(&mut json));
+		assert_eq!(json, json!({ "count": 6, "_settingsVersion": 2 }));
+	}
+
+	#[test]
+	fn applies_only_pending_migrations() {
+		let mut json = json!({ "value": 3, "_settingsVersion": 1 });
+		assert!(migrate_settings::<CounterAction>(&mut json));
+		assert_eq!(json, json!({ "count": 3, "_settingsVersion": 2 }));
+	}
+
+	#[test]
+	fn leaves_current_and_newer_settings() {
+		for version in [2, 3] {
+			let mut json = json!({ "count": 3, "_settingsVersion": version });
+			let before = json.clone();
+			assert!(!migrate_settings::<CounterAction>(&mut json));
+			assert_eq!(json, before);
 		}
-		Ok(())
+	}
+
+	#[test]
+	fn stamps_empty_settings() {
+		let mut json = json!({});
+		assert!(migrate_settings::<CounterAction>(&mut json));
+		assert_eq!(json, json!({ "_settingsVersion": 2 }));
+	}
+
+	#[test]
+	fn restores_missing_version() {
+		let mut json = json!({ "count": 4 });
+		assert!(restore_settings_version(
+			&json!({ "count": 3, "_settingsVersion": 2 }),
+			&mut json
+		));
+		assert_eq!(json, json!({ "count": 4, "_settingsVersion": 2 }));
+	}
+
+	#[test]
+	fn keeps_received_version_and_empty_settings() {
+		let current = json!({ "count": 3, "_settingsVersion": 2 });
+		let mut versioned = json!({ "value": 4, "_settingsVersion": 1 });
+		assert!(!restore_settings_version(&current, &mut versioned));
+		assert_eq!(versioned, json!({ "value": 4, "_settingsVersion": 1 }));
+		let mut empty = json!({});
+		assert!(!restore_settings_version(&current, &mut empty));
+		assert_eq!(empty, json!({}));
+		let mut unversioned = json!({ "value": 4 });
+		assert!(!restore_settings_version(&json!({ "count": 3 }), &mut unversioned));
+		assert_eq!(unversioned, json!({ "value": 4 }));
 	}
 }
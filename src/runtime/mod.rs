@@ -1,142 +1,342 @@
 mod action;
+mod errors;
+pub(crate) mod events;
+mod global_settings;
+mod handle;
 pub(crate) mod inbound;
 mod instance;
 mod outbound;
+mod plugin;
+mod property_inspector;
+mod reconnect;
 mod wrapper;
 
-pub use action::Action;
+pub use action::{Action, EncoderAction, KeypadAction};
+pub use errors::{ErrorPolicy, InvalidSettingsPolicy, set_error_policy, set_handler_timeout};
+pub(crate) use errors::{catch_panic, with_timeout};
+use events::EventBus;
+use global_settings::ErasedGlobalSettings;
+pub use global_settings::{GlobalSettings, register_global_settings};
+pub use handle::{PluginHandle, plugin_handle};
 pub use instance::Instance;
 pub use outbound::*;
-use wrapper::{ActionWrapper, ErasedAction};
+pub use plugin::{Plugin, PluginBuilder, default_plugin};
+use property_inspector::ErasedPropertyInspectorHandler;
+pub use property_inspector::{PropertyInspectorHandler, register_property_inspector_handler};
+pub use reconnect::ReconnectPolicy;
+use wrapper::{ActionWrapper, EncoderEvents, ErasedAction, Forward, Ignore, KeypadEvents};
 
 use crate::OpenActionResult as Result;
-use crate::inbound::AppearEvent;
+use crate::inbound::{
+	AppearEvent, Controller, CustomEventHandler, DeviceInfo, ErasedCustomEventHandler, Event, GenericInstancePayload,
+	RegistrationInfo, Subscribers,
+};
 use crate::outbound::OutboundEventManager;
 
-use std::collections::HashMap;
-use std::sync::{Arc, LazyLock};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
 
 use dashmap::{DashMap, DashSet};
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{Mutex, RwLock, oneshot, watch};
 
 /// UUID of an action as defined in the plugin manifest
 pub type ActionUuid = &'static str;
 /// Value uniquely identifying an instance of an action
 pub type InstanceId = String;
 
+/// State of a single plugin session
 #[derive(Default)]
-struct Runtime {
+pub(crate) struct Runtime {
 	actions: Mutex<HashMap<ActionUuid, Arc<dyn ErasedAction>>>,
 	instances: DashMap<InstanceId, Arc<Instance>>,
+	/// Instances that disappeared when the connection was lost, reused if the server replays their `willAppear`
+	disconnected: DashMap<InstanceId, Arc<Instance>>,
 	visible: DashMap<ActionUuid, DashSet<InstanceId>>,
-	outbound: Mutex<Option<OutboundEventManager>>,
+	pub(crate) devices: DashMap<String, DeviceInfo>,
+	pub(crate) global_event_handlers: Subscribers,
+	pub(crate) events: EventBus,
+	pub(crate) custom_events: DashMap<&'static str, Arc<dyn ErasedCustomEventHandler>>,
+	property_inspectors: DashMap<ActionUuid, Arc<dyn ErasedPropertyInspectorHandler>>,
+	outbound: RwLock<Option<OutboundEventManager>>,
+	outbound_buffer: Mutex<OutboundBuffer>,
+	reconnect: Mutex<ReconnectPolicy>,
+	pub(crate) dispatch_mode: Mutex<crate::inbound::DispatchMode>,
+	error_policy: Mutex<ErrorPolicy>,
+	pub(crate) handler_timeout: Mutex<Option<std::time::Duration>>,
+	registration: RwLock<Option<RegistrationInfo>>,
+	pending_settings: DashMap<InstanceId, Vec<oneshot::Sender<()>>>,
+	global_settings: Mutex<Option<Arc<dyn ErasedGlobalSettings>>>,
+	pending_global_settings: Mutex<Vec<oneshot::Sender<serde_json::Value>>>,
+	shutdown: watch::Sender<bool>,
 }
 
-static RUNTIME: LazyLock<Runtime> = LazyLock::new(Runtime::default);
+type BufferedEvent = Box<dyn FnOnce(&OutboundEventManager) -> Result<()> + Send>;
 
-pub(crate) async fn set_outbound_manager(mgr: OutboundEventManager) {
-	let mut guard = RUNTIME.outbound.lock().await;
-	*guard = Some(mgr);
+#[derive(Default)]
+struct OutboundBuffer {
+	events: VecDeque<BufferedEvent>,
+	capacity: usize,
 }
 
-/// Register the event handler for an action defined in the plugin manifest
-pub async fn register_action<A: Action>(action: A) {
-	let uuid = A::UUID;
-	let mut actions = RUNTIME.actions.lock().await;
-	if actions.contains_key(uuid) {
-		log::warn!("Action '{}' already registered", uuid);
-		return;
+impl Runtime {
+	pub(crate) async fn set_outbound_manager(&self, mgr: OutboundEventManager) {
+		let mut guard = self.outbound.write().await;
+		let buffered = std::mem::take(&mut self.outbound_buffer.lock().await.events);
+		for f in buffered {
+			if let Err(error) = f(&mgr) {
+				log::error!("Failed to send buffered event: {}", error);
+			}
+		}
+		*guard = Some(mgr);
+	}
+
+	pub(crate) async fn clear_outbound_manager(&self) {
+		self.outbound.write().await.take();
+	}
+
+	/// Queue an event using the outbound event manager, buffering it if not connected and buffering is enabled
+	async fn with_outbound(&self, f: impl FnOnce(&OutboundEventManager) -> Result<()> + Send + 'static) -> Result<()> {
+		let guard = self.outbound.read().await;
+		if let Some(mgr) = guard.as_ref() {
+			return f(mgr);
+		}
+		let mut buffer = self.outbound_buffer.lock().await;
+		if buffer.events.len() >= buffer.capacity {
+			return Err(crate::OpenActionError::NotConnected);
+		}
+		buffer.events.push_back(Box::new(f));
+		Ok(())
+	}
+
+	async fn get_action(&self, uuid: &str) -> Option<Arc<dyn ErasedAction>> {
+		let actions = self.actions.lock().await;
+		actions.get(uuid).cloned()
+	}
+
+	fn get_instance(&self, instance_id: &str) -> Option<Arc<Instance>> {
+		self.instances.get(instance_id).as_deref().cloned()
+	}
+
+	pub(crate) async fn handle_will_appear(self: &Arc<Self>, event: AppearEvent) -> Result<()> {
+		// Instances of unknown actions are still tracked so that they can be resolved for the events stream
+		let action = self.get_action(&event.action).await;
+		if action.is_none() {
+			log::warn!("Unknown action '{}' (willAppear)", event.action);
+		}
+
+		let instance_id = event.context.clone();
+		let existing = match self.instances.get(&instance_id) {
+			Some(ins) => Some(ins.clone()),
+			None => self.disconnected.remove(&instance_id).map(|(_, ins)| ins),
+		};
+
+		let mut old_settings = None;
+		let instance = if let Some(ins) = existing {
+			old_settings = ins.store_settings_json(&event.payload.settings).await;
+			self.instances.insert(instance_id.clone(), ins.clone());
+			ins
+		} else {
+			let ins = Arc::new(Instance {
+				action_uuid: event.action.clone(),
+				instance_id: instance_id.clone(),
+				device_id: event.device.clone(),
+				controller: event.payload.controller.clone(),
+				coordinates: event.payload.coordinates,
+				is_in_multi_action: event.payload.is_in_multi_action,
+				current_state_index: std::sync::atomic::AtomicU16::new(event.payload.state),
+				user_desired_state: std::sync::Mutex::new(None),
+				settings_json: RwLock::new(event.payload.settings.clone()),
+				typed_settings: std::sync::Mutex::new(None),
+				settings_watch: watch::Sender::new(event.payload.settings.clone()),
+				runtime: Arc::downgrade(self),
+			});
+			self.instances.insert(instance_id.clone(), ins.clone());
+			ins
+		};
+
+		self.events
+			.publish(|| Event::WillAppear(instance.clone(), event.clone()));
+		let Some(action) = action else {
+			return Ok(());
+		};
+		if !matches!(instance.controller, Controller::Unknown(_))
+			&& !action.controllers().contains(&instance.controller)
+		{
+			log::warn!(
+				"Action '{}' does not support the {} controller (instance '{}')",
+				action.uuid(),
+				instance.controller,
+				instance.instance_id
+			);
+		}
+		self.visible.entry(action.uuid()).or_default().insert(instance_id);
+
+		self.settings_changed(&*action, &instance, old_settings).await;
+		self.invoke(&*action, &instance, "willAppear", action.call_will_appear(&instance))
+			.await
+	}
+
+	pub(crate) async fn handle_will_disappear(&self, event: AppearEvent) -> Result<()> {
+		let instance = if let Some(entry) = self.instances.remove(&event.context) {
+			entry.1
+		} else {
+			return Ok(());
+		};
+		let old_settings = instance.store_settings_json(&event.payload.settings).await;
+		self.events
+			.publish(|| Event::WillDisappear(instance.clone(), event.clone()));
+		if let Some(action) = self.get_action(&event.action).await {
+			self.settings_changed(&*action, &instance, old_settings).await;
+			self.invoke(
+				&*action,
+				&instance,
+				"willDisappear",
+				action.call_will_disappear(&instance),
+			)
+			.await?;
+			if let Some(set) = self.visible.get(&action.uuid()) {
+				set.remove(&event.context);
+			}
+		}
+		Ok(())
+	}
+
+	/// Call the will disappear handler of every remaining instance, then write pending events and close the
+	/// connection
+	pub(crate) async fn handle_shutdown(&self) {
+		for instance in self.remove_all_instances() {
+			self.disappear(&instance, "shutdown").await;
+		}
+
+		self.outbound_buffer.lock().await.events.clear();
+		let mgr = self.outbound.write().await.take();
+		if let Some(mgr) = mgr
+			&& let Err(error) = mgr.close().await
+		{
+			log::error!("Failed to close WebSocket: {}", error);
+		}
+		self.shutdown.send_replace(false);
+	}
+
+	/// Call the will disappear handler of every instance and forget connected devices after the connection is lost
+	///
+	/// After the plugin registers again, the OpenAction server replays `willAppear` for the instances that still exist,
+	/// which reuse their previous [`Instance`], and `deviceDidConnect` for the devices that are still connected.
+	pub(crate) async fn handle_connection_lost(&self) {
+		self.devices.clear();
+		self.disconnected.clear();
+		for instance in self.remove_all_instances() {
+			self.disconnected.insert(instance.instance_id.clone(), instance.clone());
+			self.disappear(&instance, "lost connection").await;
+		}
+	}
+
+	fn remove_all_instances(&self) -> Vec<Arc<Instance>> {
+		let ids: Vec<_> = self.instances.iter().map(|entry| entry.key().clone()).collect();
+		ids.iter()
+			.filter_map(|id| self.instances.remove(id).map(|(_, instance)| instance))
+			.collect()
+	}
+
+	/// Publish a will disappear event for an instance the OpenAction server did not send one for and call the handler
+	async fn disappear(&self, instance: &Arc<Instance>, reason: &str) {
+		let payload = GenericInstancePayload {
+			settings: instance.settings_json.read().await.clone(),
+			coordinates: instance.coordinates,
+			controller: instance.controller.clone(),
+			state: instance.current_state_index.load(std::sync::atomic::Ordering::Relaxed),
+			user_desired_state: None,
+			is_in_multi_action: instance.is_in_multi_action,
+		};
+		self.events.publish(|| {
+			Event::WillDisappear(
+				instance.clone(),
+				AppearEvent {
+					action: instance.action_uuid.clone(),
+					context: instance.instance_id.clone(),
+					device: instance.device_id.clone(),
+					payload: payload.clone(),
+				},
+			)
+		});
+		let Some(action) = self.get_action(&instance.action_uuid).await else {
+			return;
+		};
+		if let Some(set) = self.visible.get(&action.uuid()) {
+			set.remove(&instance.instance_id);
+		}
+		let handler = action.call_will_disappear(instance);
+		if let Err(error) = self.invoke(&*action, instance, "willDisappear", handler).await {
+			log::error!("Failed to process {}: {}", reason, error);
+		}
 	}
-	actions.insert(uuid, Arc::new(ActionWrapper(action)));
-	RUNTIME.visible.insert(uuid, DashSet::new());
 }
 
-async fn get_action(uuid: &str) -> Option<Arc<dyn ErasedAction>> {
-	let actions = RUNTIME.actions.lock().await;
-	actions.get(uuid).cloned()
+/// Set how many outbound events may be buffered while not connected to the OpenAction server
+///
+/// Buffered events are sent as soon as the plugin is registered. Once the buffer is full, or if the capacity is
+/// zero (the default), sending while not connected fails with [`NotConnected`](crate::OpenActionError::NotConnected).
+pub async fn set_outbound_buffer_capacity(capacity: usize) {
+	default_plugin().set_outbound_buffer_capacity(capacity).await
 }
 
-async fn resolve(action_uuid: &str, instance_id: &str) -> Result<Option<(Arc<dyn ErasedAction>, Arc<Instance>)>> {
-	let Some(action) = get_action(action_uuid).await else {
-		return Ok(None);
-	};
-	let Some(instance) = RUNTIME.instances.get(instance_id) else {
-		return Ok(None);
-	};
-	Ok(Some((action, instance.clone())))
+/// Set the backoff policy used to reconnect to the OpenAction server after the connection is lost
+pub async fn set_reconnect_policy(policy: ReconnectPolicy) {
+	default_plugin().set_reconnect_policy(policy).await
 }
 
-pub(crate) async fn handle_will_appear(event: AppearEvent) -> Result<()> {
-	let Some(action) = get_action(&event.action).await else {
-		log::warn!("Unknown action '{}' (willAppear)", event.action);
-		return Ok(());
-	};
-
-	let instance_id = event.context.clone();
-	let existing = RUNTIME.instances.get(&instance_id).map(|a| a.clone());
-
-	let instance = if let Some(ins) = existing {
-		ins
-	} else {
-		let ins = Arc::new(Instance {
-			action_uuid: event.action.clone(),
-			instance_id: instance_id.clone(),
-			device_id: event.device.clone(),
-			controller: event.payload.controller.clone(),
-			coordinates: event.payload.coordinates,
-			is_in_multi_action: event.payload.is_in_multi_action,
-			current_state_index: std::sync::atomic::AtomicU16::new(event.payload.state),
-			settings_json: RwLock::new(event.payload.settings.clone()),
-		});
-		RUNTIME.instances.insert(instance_id.clone(), ins.clone());
-		ins
-	};
+/// Set how inbound events are dispatched to event handlers, taking effect from the next connection
+pub async fn set_dispatch_mode(mode: crate::inbound::DispatchMode) {
+	default_plugin().set_dispatch_mode(mode).await
+}
 
-	RUNTIME.visible.entry(action.uuid()).or_default().insert(instance_id);
+/// Register the event handler for an action defined in the plugin manifest that can be placed on keys and encoders
+pub async fn register_action<A: KeypadAction + EncoderAction>(action: A) {
+	default_plugin().register_action(action).await
+}
 
-	action.call_will_appear(&instance, event.payload).await
+/// Register the event handler for an action that can only be placed on keys
+///
+/// Encoder events for this action are rejected with a warning.
+pub async fn register_keypad_action<A: KeypadAction>(action: A) {
+	default_plugin().register_keypad_action(action).await
 }
 
-pub(crate) async fn handle_will_disappear(event: AppearEvent) -> Result<()> {
-	let instance = if let Some(entry) = RUNTIME.instances.remove(&event.context) {
-		entry.1
-	} else {
-		return Ok(());
-	};
-	if let Some(action) = get_action(&event.action).await {
-		action.call_will_disappear(&instance, event.payload).await?;
-		if let Some(set) = RUNTIME.visible.get(&action.uuid()) {
-			set.remove(&event.context);
-		}
-	}
-	Ok(())
+/// Register the event handler for an action that can only be placed on encoders
+///
+/// Key events for this action are rejected with a warning.
+pub async fn register_encoder_action<A: EncoderAction>(action: A) {
+	default_plugin().register_encoder_action(action).await
+}
+
+/// Register a handler for inbound events not defined by the OpenAction protocol
+pub fn register_custom_event_handler<H: CustomEventHandler>(handler: H) {
+	default_plugin().register_custom_event_handler(handler)
 }
 
 /// List all instances of an action currently visible to the user
 pub async fn visible_instances(action_uuid: ActionUuid) -> Vec<Arc<Instance>> {
-	RUNTIME
-		.visible
-		.get(&action_uuid)
-		.map(|set| {
-			set.iter()
-				.filter_map(|id| RUNTIME.instances.get(&*id).as_deref().cloned())
-				.collect()
-		})
-		.unwrap_or_default()
+	default_plugin().visible_instances(action_uuid).await
 }
 
 /// Get an instance of an action by its ID
 pub async fn get_instance(instance_id: InstanceId) -> Option<Arc<Instance>> {
-	RUNTIME.instances.get(&instance_id).as_deref().cloned()
+	default_plugin().get_instance(instance_id).await
 }
 
-pub(crate) static CONNECTED_DEVICES: LazyLock<DashMap<String, crate::inbound::DeviceInfo>> =
-	LazyLock::new(DashMap::new);
-
 /// List all connected devices
-pub async fn get_connected_devices() -> HashMap<String, crate::inbound::DeviceInfo> {
-	CONNECTED_DEVICES
-		.iter()
-		.map(|entry| (entry.key().clone(), entry.value().clone()))
-		.collect()
+pub async fn get_connected_devices() -> HashMap<String, DeviceInfo> {
+	default_plugin().get_connected_devices().await
+}
+
+/// Get the device an instance is bound to, if it is connected
+pub async fn device_for(instance: &Instance) -> Option<DeviceInfo> {
+	default_plugin().device_for(instance).await
+}
+
+/// Get the registration information passed to the plugin by the OpenAction server
+///
+/// Returns `None` if the plugin has not been started with [`run`](crate::run) yet.
+pub async fn registration_info() -> Option<RegistrationInfo> {
+	default_plugin().registration_info().await
 }
@@ -1,53 +1,173 @@
-use super::RUNTIME;
+use super::{Plugin, default_plugin};
 
-use crate::OpenActionResult as Result;
+use crate::{OpenActionError, OpenActionResult as Result};
 
 use serde::Serialize;
 
+impl Plugin {
+	/// Wait until every event sent so far has been written to the WebSocket
+	pub async fn flush_outbound(&self) -> Result<()> {
+		let mgr = self.runtime.outbound.read().await.clone();
+		mgr.ok_or(OpenActionError::NotConnected)?.flush().await
+	}
+
+	/// Send an arbitrary JSON-serializable event to the OpenAction server
+	pub async fn send_arbitrary_json(&self, event: impl Serialize) -> Result<()> {
+		let event = serde_json::to_value(event)?;
+		self.runtime.with_outbound(move |mgr| mgr.send_event(event)).await
+	}
+
+	/// <https://openaction.amankhanna.me/5_serverbound/settings.html#getglobalsettings>
+	pub async fn get_global_settings(&self) -> Result<()> {
+		self.runtime.with_outbound(move |mgr| mgr.get_global_settings()).await
+	}
+
+	/// <https://openaction.amankhanna.me/5_serverbound/settings.html#setglobalsettings>
+	pub async fn set_global_settings(&self, value: impl Serialize) -> Result<()> {
+		let value = serde_json::to_value(value)?;
+		let sent = value.clone();
+		self.runtime
+			.with_outbound(move |mgr| mgr.set_global_settings(sent))
+			.await?;
+		self.runtime.receive_global_settings(&value).await;
+		Ok(())
+	}
+
+	/// <https://openaction.amankhanna.me/5_serverbound/misc.html#openurl>
+	pub async fn open_url(&self, url: String) -> Result<()> {
+		self.runtime.with_outbound(move |mgr| mgr.open_url(url)).await
+	}
+
+	/// <https://openaction.amankhanna.me/5_serverbound/misc.html#logmessage>
+	pub async fn log_message(&self, message: String) -> Result<()> {
+		self.runtime.with_outbound(move |mgr| mgr.log_message(message)).await
+	}
+
+	/// Switch a device to a profile bundled with this plugin, optionally opening a specific page of it
+	///
+	/// Passing `None` as the profile switches the device back to the profile it was using before.
+	pub async fn switch_to_profile(&self, device: String, profile: Option<String>, page: Option<u16>) -> Result<()> {
+		self.runtime
+			.with_outbound(move |mgr| mgr.switch_to_profile(device, profile, page))
+			.await
+	}
+
+	/// Outbound events sent by plugins that add support for new devices
+	pub fn device_plugin(&self) -> DevicePlugin<'_> {
+		DevicePlugin(self)
+	}
+}
+
+/// Wait until every event sent so far has been written to the WebSocket
+pub async fn flush_outbound() -> Result<()> {
+	default_plugin().flush_outbound().await
+}
+
 /// Send an arbitrary JSON-serializable event to the OpenAction server
 pub async fn send_arbitrary_json(event: impl Serialize) -> Result<()> {
-	if let Some(mgr) = RUNTIME.outbound.lock().await.as_mut() {
-		mgr.send_event(event).await?;
-	}
-	Ok(())
+	default_plugin().send_arbitrary_json(event).await
 }
 
 /// <https://openaction.amankhanna.me/5_serverbound/settings.html#getglobalsettings>
 pub async fn get_global_settings() -> Result<()> {
-	if let Some(mgr) = RUNTIME.outbound.lock().await.as_mut() {
-		mgr.get_global_settings().await?;
-	}
-	Ok(())
+	default_plugin().get_global_settings().await
 }
 
 /// <https://openaction.amankhanna.me/5_serverbound/settings.html#setglobalsettings>
 pub async fn set_global_settings(value: impl Serialize) -> Result<()> {
-	let value = serde_json::to_value(value)?;
-	if let Some(mgr) = RUNTIME.outbound.lock().await.as_mut() {
-		mgr.set_global_settings(value).await?;
-	}
-	Ok(())
+	default_plugin().set_global_settings(value).await
 }
 
 /// <https://openaction.amankhanna.me/5_serverbound/misc.html#openurl>
 pub async fn open_url(url: String) -> Result<()> {
-	if let Some(mgr) = RUNTIME.outbound.lock().await.as_mut() {
-		mgr.open_url(url).await?;
-	}
-	Ok(())
+	default_plugin().open_url(url).await
 }
 
 /// <https://openaction.amankhanna.me/5_serverbound/misc.html#logmessage>
 pub async fn log_message(message: String) -> Result<()> {
-	if let Some(mgr) = RUNTIME.outbound.lock().await.as_mut() {
-		mgr.log_message(message).await?;
+	default_plugin().log_message(message).await
+}
+
+/// Switch a device to a profile bundled with this plugin, optionally opening a specific page of it
+///
+/// Passing `None` as the profile switches the device back to the profile it was using before.
+pub async fn switch_to_profile(device: String, profile: Option<String>, page: Option<u16>) -> Result<()> {
+	default_plugin().switch_to_profile(device, profile, page).await
+}
+
+/// Outbound events sent by plugins that add support for new devices
+pub struct DevicePlugin<'a>(&'a Plugin);
+
+impl DevicePlugin<'_> {
+	pub async fn register_device(
+		&self,
+		id: String,
+		name: String,
+		rows: u8,
+		columns: u8,
+		encoders: u8,
+		r#type: u8,
+	) -> Result<()> {
+		self.0
+			.runtime
+			.with_outbound(move |mgr| mgr.register_device(id, name, rows, columns, encoders, r#type))
+			.await
+	}
+
+	pub async fn unregister_device(&self, id: String) -> Result<()> {
+		self.0.runtime.with_outbound(move |mgr| mgr.deregister_device(id)).await
+	}
+
+	pub async fn rerender_images(&self, id: String) -> Result<()> {
+		self.0.runtime.with_outbound(move |mgr| mgr.rerender_images(id)).await
+	}
+
+	pub async fn key_down(&self, device: String, position: u8) -> Result<()> {
+		self.0
+			.runtime
+			.with_outbound(move |mgr| mgr.key_down(device, position))
+			.await
+	}
+
+	pub async fn key_up(&self, device: String, position: u8) -> Result<()> {
+		self.0
+			.runtime
+			.with_outbound(move |mgr| mgr.key_up(device, position))
+			.await
+	}
+
+	pub async fn encoder_change(&self, device: String, position: u8, ticks: i16) -> Result<()> {
+		self.0
+			.runtime
+			.with_outbound(move |mgr| mgr.encoder_change(device, position, ticks))
+			.await
+	}
+
+	pub async fn encoder_down(&self, device: String, position: u8) -> Result<()> {
+		self.0
+			.runtime
+			.with_outbound(move |mgr| mgr.encoder_down(device, position))
+			.await
+	}
+
+	pub async fn encoder_up(&self, device: String, position: u8) -> Result<()> {
+		self.0
+			.runtime
+			.with_outbound(move |mgr| mgr.encoder_up(device, position))
+			.await
+	}
+
+	pub async fn touchscreen_press(&self, device: String, position: u8, x: u16, y: u16, hold: bool) -> Result<()> {
+		self.0
+			.runtime
+			.with_outbound(move |mgr| mgr.touchscreen_press(device, position, x, y, hold))
+			.await
 	}
-	Ok(())
 }
 
 /// Outbound events sent by plugins that add support for new devices
 pub mod device_plugin {
-	use super::{RUNTIME, Result};
+	use super::{Result, default_plugin};
 
 	pub async fn register_device(
 		id: String,
@@ -57,65 +177,47 @@ pub mod device_plugin {
 		encoders: u8,
 		r#type: u8,
 	) -> Result<()> {
-		if let Some(mgr) = RUNTIME.outbound.lock().await.as_mut() {
-			mgr.register_device(id, name, rows, columns, encoders, r#type).await?;
-		}
-		Ok(())
+		default_plugin()
+			.device_plugin()
+			.register_device(id, name, rows, columns, encoders, r#type)
+			.await
 	}
 
 	pub async fn unregister_device(id: String) -> Result<()> {
-		if let Some(mgr) = RUNTIME.outbound.lock().await.as_mut() {
-			mgr.deregister_device(id).await?;
-		}
-		Ok(())
+		default_plugin().device_plugin().unregister_device(id).await
 	}
 
 	pub async fn rerender_images(id: String) -> Result<()> {
-		if let Some(mgr) = RUNTIME.outbound.lock().await.as_mut() {
-			mgr.rerender_images(id).await?;
-		}
-		Ok(())
+		default_plugin().device_plugin().rerender_images(id).await
 	}
 
 	pub async fn key_down(device: String, position: u8) -> Result<()> {
-		if let Some(mgr) = RUNTIME.outbound.lock().await.as_mut() {
-			mgr.key_down(device, position).await?;
-		}
-		Ok(())
+		default_plugin().device_plugin().key_down(device, position).await
 	}
 
 	pub async fn key_up(device: String, position: u8) -> Result<()> {
-		if let Some(mgr) = RUNTIME.outbound.lock().await.as_mut() {
-			mgr.key_up(device, position).await?;
-		}
-		Ok(())
+		default_plugin().device_plugin().key_up(device, position).await
 	}
 
 	pub async fn encoder_change(device: String, position: u8, ticks: i16) -> Result<()> {
-		if let Some(mgr) = RUNTIME.outbound.lock().await.as_mut() {
-			mgr.encoder_change(device, position, ticks).await?;
-		}
-		Ok(())
+		default_plugin()
+			.device_plugin()
+			.encoder_change(device, position, ticks)
+			.await
 	}
 
 	pub async fn encoder_down(device: String, position: u8) -> Result<()> {
-		if let Some(mgr) = RUNTIME.outbound.lock().await.as_mut() {
-			mgr.encoder_down(device, position).await?;
-		}
-		Ok(())
+		default_plugin().device_plugin().encoder_down(device, position).await
 	}
 
 	pub async fn encoder_up(device: String, position: u8) -> Result<()> {
-		if let Some(mgr) = RUNTIME.outbound.lock().await.as_mut() {
-			mgr.encoder_up(device, position).await?;
-		}
-		Ok(())
+		default_plugin().device_plugin().encoder_up(device, position).await
 	}
 
 	pub async fn touchscreen_press(device: String, position: u8, x: u16, y: u16, hold: bool) -> Result<()> {
-		if let Some(mgr) = RUNTIME.outbound.lock().await.as_mut() {
-			mgr.touchscreen_press(device, position, x, y, hold).await?;
-		}
-		Ok(())
+		default_plugin()
+			.device_plugin()
+			.touchscreen_press(device, position, x, y, hold)
+			.await
 	}
 }
@@ -0,0 +1,330 @@
+use super::{
+	Action, ActionUuid, ActionWrapper, EncoderAction, EncoderEvents, ErrorPolicy, Forward, Ignore, Instance,
+	InstanceId, KeypadAction, KeypadEvents, PluginHandle, ReconnectPolicy, Runtime,
+};
+
+use crate::inbound::{
+	self, Controller, CustomEventHandler, CustomEventWrapper, DeviceInfo, DispatchMode, GlobalEventHandler,
+	GlobalEventSubscription, RegistrationInfo, Subscribers,
+};
+use crate::outbound::OutboundEventManager;
+use crate::{OpenActionResult as Result, PluginArgs};
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use dashmap::DashSet;
+use futures_util::StreamExt;
+use tokio_tungstenite::connect_async;
+
+/// A plugin session with its own actions, instances, devices and connection to the OpenAction server
+///
+/// The free functions of this crate operate on the [`default_plugin`]. Construct additional plugins with
+/// [`Plugin::builder`] to run several sessions in one process.
+#[derive(Clone)]
+pub struct Plugin {
+	pub(crate) runtime: Arc<Runtime>,
+}
+
+static DEFAULT_PLUGIN: LazyLock<Plugin> = LazyLock::new(|| Plugin::builder().build());
+
+/// Get the plugin used by the free functions of this crate
+pub fn default_plugin() -> &'static Plugin {
+	&DEFAULT_PLUGIN
+}
+
+/// A builder for a [`Plugin`]
+#[derive(Default)]
+pub struct PluginBuilder {
+	reconnect_policy: ReconnectPolicy,
+	dispatch_mode: DispatchMode,
+	error_policy: ErrorPolicy,
+	handler_timeout: Option<Duration>,
+	outbound_buffer_capacity: usize,
+	global_event_handlers: Vec<Arc<dyn GlobalEventHandler>>,
+}
+
+impl PluginBuilder {
+	/// Set the backoff policy used to reconnect to the OpenAction server after the connection is lost
+	pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+		self.reconnect_policy = policy;
+		self
+	}
+
+	/// Set how inbound events are dispatched to event handlers
+	pub fn dispatch_mode(mut self, mode: DispatchMode) -> Self {
+		self.dispatch_mode = mode;
+		self
+	}
+
+	/// Set the error policy used for actions that do not override [`Action::error_policy`]
+	pub fn error_policy(mut self, policy: ErrorPolicy) -> Self {
+		self.error_policy = policy;
+		self
+	}
+
+	/// Set the default maximum time an event handler may run before it is abandoned
+	pub fn handler_timeout(mut self, timeout: Duration) -> Self {
+		self.handler_timeout = Some(timeout);
+		self
+	}
+
+	/// Set how many outbound events may be buffered while not connected to the OpenAction server
+	pub fn outbound_buffer_capacity(mut self, capacity: usize) -> Self {
+		self.outbound_buffer_capacity = capacity;
+		self
+	}
+
+	/// Add a handler for global events, called after any handlers added before it
+	pub fn global_event_handler(mut self, handler: Arc<dyn GlobalEventHandler>) -> Self {
+		self.global_event_handlers.push(handler);
+		self
+	}
+
+	pub fn build(self) -> Plugin {
+		let mut runtime = Runtime::default();
+		*runtime.reconnect.get_mut() = self.reconnect_policy;
+		*runtime.dispatch_mode.get_mut() = self.dispatch_mode;
+		*runtime.error_policy.get_mut() = self.error_policy;
+		*runtime.handler_timeout.get_mut() = self.handler_timeout;
+		runtime.outbound_buffer.get_mut().capacity = self.outbound_buffer_capacity;
+		for handler in self.global_event_handlers {
+			runtime.global_event_handlers.add(handler);
+		}
+		Plugin {
+			runtime: Arc::new(runtime),
+		}
+	}
+}
+
+impl Plugin {
+	pub fn builder() -> PluginBuilder {
+		PluginBuilder::default()
+	}
+
+	/// Get a handle for stopping this plugin
+	pub fn handle(&self) -> PluginHandle {
+		PluginHandle {
+			runtime: self.runtime.clone(),
+		}
+	}
+
+	/// Register the event handler for an action defined in the plugin manifest that can be placed on keys and encoders
+	pub async fn register_action<A: KeypadAction + EncoderAction>(&self, action: A) {
+		self.insert_action::<A, Forward, Forward>(action, &[Controller::Keypad, Controller::Encoder])
+			.await
+	}
+
+	/// Register the event handler for an action that can only be placed on keys
+	///
+	/// Encoder events for this action are rejected with a warning.
+	pub async fn register_keypad_action<A: KeypadAction>(&self, action: A) {
+		self.insert_action::<A, Forward, Ignore>(action, &[Controller::Keypad])
+			.await
+	}
+
+	/// Register the event handler for an action that can only be placed on encoders
+	///
+	/// Key events for this action are rejected with a warning.
+	pub async fn register_encoder_action<A: EncoderAction>(&self, action: A) {
+		self.insert_action::<A, Ignore, Forward>(action, &[Controller::Encoder])
+			.await
+	}
+
+	async fn insert_action<A: Action, K: KeypadEvents<A>, E: EncoderEvents<A>>(
+		&self,
+		action: A,
+		controllers: &'static [Controller],
+	) {
+		let uuid = A::UUID;
+		let mut actions = self.runtime.actions.lock().await;
+		if actions.contains_key(uuid) {
+			log::warn!("Action '{}' already registered", uuid);
+			return;
+		}
+		actions.insert(
+			uuid,
+			Arc::new(ActionWrapper::<A, K, E> {
+				action,
+				controllers,
+				events: PhantomData,
+			}),
+		);
+		self.runtime.visible.insert(uuid, DashSet::new());
+	}
+
+	/// Register a handler for inbound events not defined by the OpenAction protocol
+	pub fn register_custom_event_handler<H: CustomEventHandler>(&self, handler: H) {
+		let custom_events = &self.runtime.custom_events;
+		if custom_events.contains_key(H::EVENT) {
+			log::warn!("Custom event handler for '{}' already registered", H::EVENT);
+			return;
+		}
+		custom_events.insert(H::EVENT, Arc::new(CustomEventWrapper(handler)));
+	}
+
+	/// Register the handler for global events (does nothing if already set)
+	///
+	/// Use [`subscribe_global_events`](Self::subscribe_global_events) to register more than one handler.
+	pub fn set_global_event_handler(&self, handler: &'static dyn GlobalEventHandler) {
+		self.runtime.global_event_handlers.set_static(handler);
+	}
+
+	/// Subscribe a handler to global events until the returned subscription is dropped
+	///
+	/// Handlers are called in the order they were subscribed.
+	pub fn subscribe_global_events(&self, handler: Arc<dyn GlobalEventHandler>) -> GlobalEventSubscription {
+		Subscribers::subscribe(&self.runtime, handler)
+	}
+
+	/// Set how many outbound events may be buffered while not connected to the OpenAction server
+	///
+	/// See [`set_outbound_buffer_capacity`](crate::set_outbound_buffer_capacity).
+	pub async fn set_outbound_buffer_capacity(&self, capacity: usize) {
+		let mut buffer = self.runtime.outbound_buffer.lock().await;
+		buffer.capacity = capacity;
+		buffer.events.truncate(capacity);
+	}
+
+	/// Set the backoff policy used to reconnect to the OpenAction server after the connection is lost
+	pub async fn set_reconnect_policy(&self, policy: ReconnectPolicy) {
+		*self.runtime.reconnect.lock().await = policy;
+	}
+
+	/// Set how inbound events are dispatched to event handlers, taking effect from the next connection
+	pub async fn set_dispatch_mode(&self, mode: DispatchMode) {
+		*self.runtime.dispatch_mode.lock().await = mode;
+	}
+
+	/// Set the error policy used for actions that do not override [`Action::error_policy`]
+	pub async fn set_error_policy(&self, policy: ErrorPolicy) {
+		*self.runtime.error_policy.lock().await = policy;
+	}
+
+	/// Set the default maximum time an event handler may run before it is abandoned, or `None` for no limit
+	pub async fn set_handler_timeout(&self, timeout: Option<Duration>) {
+		*self.runtime.handler_timeout.lock().await = timeout;
+	}
+
+	/// List all instances of an action currently visible to the user
+	pub async fn visible_instances(&self, action_uuid: ActionUuid) -> Vec<Arc<Instance>> {
+		self.runtime
+			.visible
+			.get(&action_uuid)
+			.map(|set| {
+				set.iter()
+					.filter_map(|id| self.runtime.instances.get(&*id).as_deref().cloned())
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	/// Get an instance of an action by its ID
+	pub async fn get_instance(&self, instance_id: InstanceId) -> Option<Arc<Instance>> {
+		self.runtime.instances.get(&instance_id).as_deref().cloned()
+	}
+
+	/// List all connected devices
+	pub async fn get_connected_devices(&self) -> HashMap<String, DeviceInfo> {
+		self.runtime
+			.devices
+			.iter()
+			.map(|entry| (entry.key().clone(), entry.value().clone()))
+			.collect()
+	}
+
+	/// Get the device an instance is bound to, if it is connected
+	pub async fn device_for(&self, instance: &Instance) -> Option<DeviceInfo> {
+		self.runtime.devices.get(&instance.device_id).as_deref().cloned()
+	}
+
+	/// Get the registration information passed to the plugin by the OpenAction server
+	///
+	/// Returns `None` if the plugin has not been started yet.
+	pub async fn registration_info(&self) -> Option<RegistrationInfo> {
+		self.runtime.registration.read().await.clone()
+	}
+
+	/// Register the plugin and run the plugin event loop
+	///
+	/// See [`run`](crate::run) for details.
+	pub async fn run(&self, args: Vec<String>) -> Result<()> {
+		self.run_with_args(PluginArgs::parse(args)?).await
+	}
+
+	/// Register the plugin and run the plugin event loop using already-parsed [`PluginArgs`]
+	///
+	/// See [`run`](crate::run) for details.
+	pub async fn run_with_args(&self, args: PluginArgs) -> Result<()> {
+		let runtime = &self.runtime;
+		let PluginArgs {
+			port,
+			plugin_uuid: uuid,
+			register_event: event,
+			info,
+		} = args;
+		let info: RegistrationInfo = serde_json::from_str(&info)?;
+		for device in &info.devices {
+			runtime.devices.insert(device.id.clone(), device.clone());
+		}
+		*runtime.registration.write().await = Some(info);
+
+		let policy = runtime.reconnect.lock().await.clone();
+		let mut attempt = 0;
+		let mut connected = false;
+		loop {
+			let error = match connect(runtime, port, &uuid, &event).await {
+				Ok(read) => {
+					attempt = 0;
+					let shutdown = inbound::process_incoming_messages(runtime, read, connected).await;
+					if shutdown {
+						runtime.handle_shutdown().await;
+						return Ok(());
+					}
+					connected = true;
+					runtime.clear_outbound_manager().await;
+					runtime.handle_connection_lost().await;
+					inbound::handle_disconnect(runtime).await;
+					None
+				}
+				// Only retry once the plugin has registered, so that a wrong port fails immediately
+				Err(error) if !connected => return Err(error),
+				Err(error) => Some(error),
+			};
+
+			let Some(delay) = policy.delay(attempt) else {
+				return error.map_or(Ok(()), Err);
+			};
+			match &error {
+				Some(error) => log::warn!(
+					"Failed to connect to OpenAction server, retrying in {:?}: {}",
+					delay,
+					error
+				),
+				None => log::warn!("Lost connection to OpenAction server, reconnecting in {:?}", delay),
+			}
+			tokio::select! {
+				() = tokio::time::sleep(delay) => {}
+				() = runtime.shutdown_requested() => {
+					runtime.handle_shutdown().await;
+					return Ok(());
+				}
+			}
+			attempt += 1;
+		}
+	}
+}
+
+async fn connect(runtime: &Runtime, port: u16, uuid: &str, event: &str) -> Result<inbound::Stream> {
+	let socket = connect_async(format!("ws://localhost:{}", port)).await?.0;
+	let (write, read) = socket.split();
+
+	let outbound = OutboundEventManager::new(write, uuid.to_owned());
+	outbound.register(event.to_owned())?;
+	outbound.flush().await?;
+	runtime.set_outbound_manager(outbound).await;
+
+	Ok(read)
+}
@@ -0,0 +1,79 @@
+use super::{Instance, Plugin, default_plugin};
+
+use crate::{OpenActionError, OpenActionResult as Result};
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Serialize, de::DeserializeOwned};
+
+#[async_trait]
+/// Typed messages exchanged with the property inspector of an action
+///
+/// Messages the property inspector sends with `sendToPlugin` are deserialized into [`Self::ToPlugin`] and passed to
+/// [`handle`](Self::handle) after [`Action::send_to_plugin`](crate::Action::send_to_plugin) has been called with the
+/// raw payload. Messages for the property inspector are sent with [`Instance::message_property_inspector`].
+pub trait PropertyInspectorHandler: Send + Sync + 'static {
+	/// UUID of the action whose property inspector this handler talks to
+	const UUID: &'static str;
+
+	/// Type of the messages sent by the property inspector
+	type ToPlugin: DeserializeOwned + Send + 'static;
+
+	/// Type of the messages sent to the property inspector
+	type ToPropertyInspector: Serialize + Send + Sync + 'static;
+
+	async fn handle(&self, instance: &Instance, message: Self::ToPlugin) -> Result<()>;
+}
+
+#[async_trait]
+pub(super) trait ErasedPropertyInspectorHandler: Send + Sync {
+	async fn call(&self, instance: &Instance, payload: serde_json::Value) -> Result<()>;
+}
+
+struct PropertyInspectorWrapper<H: PropertyInspectorHandler>(H);
+
+#[async_trait]
+impl<H: PropertyInspectorHandler> ErasedPropertyInspectorHandler for PropertyInspectorWrapper<H> {
+	async fn call(&self, instance: &Instance, payload: serde_json::Value) -> Result<()> {
+		let message = serde_json::from_value(payload)?;
+		self.0.handle(instance, message).await
+	}
+}
+
+impl Plugin {
+	/// Register the handler for typed messages from the property inspector of a registered action
+	pub fn register_property_inspector_handler<H: PropertyInspectorHandler>(&self, handler: H) {
+		let handlers = &self.runtime.property_inspectors;
+		if handlers.contains_key(H::UUID) {
+			log::warn!("Property inspector handler for '{}' already registered", H::UUID);
+			return;
+		}
+		handlers.insert(H::UUID, Arc::new(PropertyInspectorWrapper(handler)));
+	}
+}
+
+/// Register the handler for typed messages from the property inspector of a registered action
+pub fn register_property_inspector_handler<H: PropertyInspectorHandler>(handler: H) {
+	default_plugin().register_property_inspector_handler(handler)
+}
+
+impl Instance {
+	/// Send a message of the type the property inspector handler `H` declares to the property inspector of this
+	/// instance
+	///
+	/// Fails with [`ActionMismatch`](OpenActionError::ActionMismatch) if this is not an instance of the action `H`
+	/// handles.
+	pub async fn message_property_inspector<H: PropertyInspectorHandler>(
+		&self,
+		message: &H::ToPropertyInspector,
+	) -> Result<()> {
+		if self.action_uuid != H::UUID {
+			return Err(OpenActionError::ActionMismatch {
+				expected: H::UUID,
+				action: self.action_uuid.clone(),
+			});
+		}
+		self.send_to_property_inspector(message).await
+	}
+}
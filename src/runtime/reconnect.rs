@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+/// Exponential backoff used by [`run`](crate::run) to reconnect after the connection to the OpenAction server is lost
+///
+/// The policy only applies once the plugin has connected successfully; if the first connection fails, `run` returns
+/// the error straight away.
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+	/// Maximum number of consecutive reconnection attempts, or `None` to retry indefinitely
+	pub max_attempts: Option<u32>,
+	/// Delay before the first reconnection attempt
+	pub initial_delay: Duration,
+	/// Upper bound for the delay between reconnection attempts
+	pub max_delay: Duration,
+	/// Factor the delay is multiplied by after each failed attempt
+	pub multiplier: f64,
+}
+
+impl Default for ReconnectPolicy {
+	fn default() -> Self {
+		Self {
+			max_attempts: None,
+			initial_delay: Duration::from_millis(500),
+			max_delay: Duration::from_secs(30),
+			multiplier: 2.0,
+		}
+	}
+}
+
+impl ReconnectPolicy {
+	/// A policy that never reconnects, returning from [`run`](crate::run) as soon as the connection is lost
+	pub fn disabled() -> Self {
+		Self {
+			max_attempts: Some(0),
+			..Default::default()
+		}
+	}
+
+	/// The delay before the given (zero-based) reconnection attempt, or `None` if no more attempts should be made
+	pub(crate) fn delay(&self, attempt: u32) -> Option<Duration> {
+		if self.max_attempts.is_some_and(|max| attempt >= max) {
+			return None;
+		}
+		let factor = self.multiplier.max(1.0).powi(attempt.min(i32::MAX as u32) as i32);
+		let delay = self.initial_delay.as_secs_f64() * factor;
+		Some(Duration::try_from_secs_f64(delay).map_or(self.max_delay, |delay| delay.min(self.max_delay)))
+	}
+}
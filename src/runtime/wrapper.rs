@@ -1,26 +1,33 @@
-use super::action::Action;
-use super::{ActionUuid, Instance};
+use super::action::{Action, EncoderAction, KeypadAction};
+use super::instance::{InvalidSettings, deserialize_settings, migrate_settings};
+use super::{ActionUuid, ErrorPolicy, Instance, InvalidSettingsPolicy};
 
-use crate::OpenActionResult as Result;
-use crate::inbound::{
-	DialPressPayload, DialRotatePayload, GenericInstancePayload, TitleParametersDidChangePayload, TouchTapPayload,
-};
+use crate::inbound::{Controller, DialRotatePayload, TitleParametersDidChangePayload, TouchTapPayload};
+use crate::{OpenActionError, OpenActionResult as Result};
+
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::time::Duration;
 
 use async_trait::async_trait;
 
 #[async_trait]
 pub(super) trait ErasedAction: Send + Sync {
 	fn uuid(&self) -> ActionUuid;
+	fn controllers(&self) -> &'static [Controller];
+	fn error_policy(&self) -> Option<ErrorPolicy>;
+	fn handler_timeout(&self) -> Option<Duration>;
 
-	async fn call_will_appear(&self, instance: &Instance, event: GenericInstancePayload) -> Result<()>;
-	async fn call_will_disappear(&self, instance: &Instance, event: GenericInstancePayload) -> Result<()>;
-	async fn call_key_down(&self, instance: &Instance, event: GenericInstancePayload) -> Result<()>;
-	async fn call_key_up(&self, instance: &Instance, event: GenericInstancePayload) -> Result<()>;
+	async fn call_will_appear(&self, instance: &Instance) -> Result<()>;
+	async fn call_will_disappear(&self, instance: &Instance) -> Result<()>;
+	async fn call_key_down(&self, instance: &Instance) -> Result<()>;
+	async fn call_key_up(&self, instance: &Instance) -> Result<()>;
 	async fn call_dial_rotate(&self, instance: &Instance, event: DialRotatePayload) -> Result<()>;
-	async fn call_dial_down(&self, instance: &Instance, event: DialPressPayload) -> Result<()>;
-	async fn call_dial_up(&self, instance: &Instance, event: DialPressPayload) -> Result<()>;
+	async fn call_dial_down(&self, instance: &Instance) -> Result<()>;
+	async fn call_dial_up(&self, instance: &Instance) -> Result<()>;
 	async fn call_touch_tap(&self, instance: &Instance, event: TouchTapPayload) -> Result<()>;
-	async fn call_did_receive_settings(&self, instance: &Instance, event: GenericInstancePayload) -> Result<()>;
+	async fn call_did_receive_settings(&self, instance: &Instance) -> Result<()>;
 	async fn call_title_parameters_did_change(
 		&self,
 		instance: &Instance,
@@ -29,71 +36,246 @@ pub(super) trait ErasedAction: Send + Sync {
 	async fn call_pi_did_appear(&self, instance: &Instance) -> Result<()>;
 	async fn call_pi_did_disappear(&self, instance: &Instance) -> Result<()>;
 	async fn call_send_to_plugin(&self, instance: &Instance, payload: serde_json::Value) -> Result<()>;
+	async fn call_settings_changed(&self, instance: &Instance, old: serde_json::Value) -> Result<()>;
+	async fn call_on_error(&self, instance: &Instance, error: &OpenActionError) -> Result<()>;
 }
 
-fn deserialize_settings<A: Action>(json: serde_json::Value) -> A::Settings {
-	match serde_json::from_value(json) {
-		Ok(settings) => settings,
-		Err(error) => {
-			log::error!("Failed to deserialize settings for action {}: {}", A::UUID, error);
-			Default::default()
-		}
+pub(super) struct ActionWrapper<A: Action, K, E> {
+	pub(super) action: A,
+	/// Controllers this action may be placed on
+	pub(super) controllers: &'static [Controller],
+	/// How key (`K`) and encoder (`E`) events are forwarded to the action
+	pub(super) events: PhantomData<fn() -> (K, E)>,
+}
+
+/// Forwards key events to an action that implements [`KeypadAction`], or ignores them
+#[async_trait]
+pub(super) trait KeypadEvents<A: Action>: 'static {
+	async fn key_down(action: &A, instance: &Instance, settings: &A::Settings) -> Result<()>;
+	async fn key_up(action: &A, instance: &Instance, settings: &A::Settings) -> Result<()>;
+}
+
+/// Forwards dial and touch strip events to an action that implements [`EncoderAction`], or ignores them
+#[async_trait]
+pub(super) trait EncoderEvents<A: Action>: 'static {
+	async fn dial_rotate(
+		action: &A,
+		instance: &Instance,
+		settings: &A::Settings,
+		event: DialRotatePayload,
+	) -> Result<()>;
+	async fn dial_down(action: &A, instance: &Instance, settings: &A::Settings) -> Result<()>;
+	async fn dial_up(action: &A, instance: &Instance, settings: &A::Settings) -> Result<()>;
+	async fn touch_tap(action: &A, instance: &Instance, settings: &A::Settings, event: TouchTapPayload) -> Result<()>;
+}
+
+/// Forward events for a controller to the action
+pub(super) struct Forward;
+/// Ignore events for a controller, which are rejected before reaching the action anyway
+pub(super) struct Ignore;
+
+#[async_trait]
+impl<A: KeypadAction> KeypadEvents<A> for Forward {
+	async fn key_down(action: &A, instance: &Instance, settings: &A::Settings) -> Result<()> {
+		action.key_down(instance, settings).await
+	}
+
+	async fn key_up(action: &A, instance: &Instance, settings: &A::Settings) -> Result<()> {
+		action.key_up(instance, settings).await
+	}
+}
+
+#[async_trait]
+impl<A: Action> KeypadEvents<A> for Ignore {
+	async fn key_down(_action: &A, _instance: &Instance, _settings: &A::Settings) -> Result<()> {
+		Ok(())
+	}
+
+	async fn key_up(_action: &A, _instance: &Instance, _settings: &A::Settings) -> Result<()> {
+		Ok(())
 	}
 }
 
-pub(super) struct ActionWrapper<A: Action>(pub(super) A);
+#[async_trait]
+impl<A: EncoderAction> EncoderEvents<A> for Forward {
+	async fn dial_rotate(
+		action: &A,
+		instance: &Instance,
+		settings: &A::Settings,
+		event: DialRotatePayload,
+	) -> Result<()> {
+		action.dial_rotate(instance, settings, event.ticks, event.pressed).await
+	}
+
+	async fn dial_down(action: &A, instance: &Instance, settings: &A::Settings) -> Result<()> {
+		action.dial_down(instance, settings).await
+	}
+
+	async fn dial_up(action: &A, instance: &Instance, settings: &A::Settings) -> Result<()> {
+		action.dial_up(instance, settings).await
+	}
+
+	async fn touch_tap(action: &A, instance: &Instance, settings: &A::Settings, event: TouchTapPayload) -> Result<()> {
+		action.touch_tap(instance, settings, event.tapPos, event.hold).await
+	}
+}
 
 #[async_trait]
-impl<A: Action> ErasedAction for ActionWrapper<A> {
+impl<A: Action> EncoderEvents<A> for Ignore {
+	async fn dial_rotate(
+		_action: &A,
+		_instance: &Instance,
+		_settings: &A::Settings,
+		_event: DialRotatePayload,
+	) -> Result<()> {
+		Ok(())
+	}
+
+	async fn dial_down(_action: &A, _instance: &Instance, _settings: &A::Settings) -> Result<()> {
+		Ok(())
+	}
+
+	async fn dial_up(_action: &A, _instance: &Instance, _settings: &A::Settings) -> Result<()> {
+		Ok(())
+	}
+
+	async fn touch_tap(
+		_action: &A,
+		_instance: &Instance,
+		_settings: &A::Settings,
+		_event: TouchTapPayload,
+	) -> Result<()> {
+		Ok(())
+	}
+}
+
+impl<A: Action, K, E> ActionWrapper<A, K, E> {
+	/// The settings of an instance, applying the action's [`InvalidSettingsPolicy`] if they cannot be deserialized
+	///
+	/// Returns `None` if the event handler should not be called.
+	async fn settings(&self, instance: &Instance) -> Result<Option<Arc<A::Settings>>> {
+		let invalid = match instance.typed_settings::<A>().await {
+			Ok(settings) => return Ok(Some(settings)),
+			Err(invalid) => invalid,
+		};
+		let raw = instance.settings_json.read().await.clone();
+		let settings = self.invalid_settings(instance, &raw, &invalid).await?;
+		if let Some(settings) = &settings {
+			instance.replace_invalid_settings::<A>(&invalid, settings.clone());
+		}
+		Ok(settings)
+	}
+
+	/// Apply the action's [`InvalidSettingsPolicy`] to settings that could not be deserialized
+	async fn invalid_settings(
+		&self,
+		instance: &Instance,
+		raw: &serde_json::Value,
+		invalid: &InvalidSettings,
+	) -> Result<Option<Arc<A::Settings>>> {
+		match self.action.invalid_settings_policy() {
+			InvalidSettingsPolicy::UseDefault => Ok(Some(Arc::default())),
+			InvalidSettingsPolicy::SkipEvent => Ok(None),
+			InvalidSettingsPolicy::Hook => {
+				let settings = self.action.invalid_settings(instance, raw, &invalid.error).await?;
+				Ok(settings.map(Arc::new))
+			}
+			InvalidSettingsPolicy::NotifyPropertyInspector => {
+				if !invalid.notified.swap(true, Relaxed) {
+					let message = serde_json::json!({
+						"event": "invalidSettings",
+						"error": invalid.error.to_string(),
+					});
+					if let Err(error) = instance.send_to_property_inspector(message).await {
+						invalid.notified.store(false, Relaxed);
+						return Err(error);
+					}
+				}
+				Ok(None)
+			}
+		}
+	}
+}
+
+#[async_trait]
+impl<A: Action, K: KeypadEvents<A>, E: EncoderEvents<A>> ErasedAction for ActionWrapper<A, K, E> {
 	fn uuid(&self) -> ActionUuid {
 		A::UUID
 	}
 
-	async fn call_will_appear(&self, instance: &Instance, event: GenericInstancePayload) -> Result<()> {
-		let settings = deserialize_settings::<A>(event.settings);
-		self.0.will_appear(instance, &settings).await
+	fn controllers(&self) -> &'static [Controller] {
+		self.controllers
+	}
+
+	fn error_policy(&self) -> Option<ErrorPolicy> {
+		self.action.error_policy()
+	}
+
+	fn handler_timeout(&self) -> Option<Duration> {
+		self.action.handler_timeout()
+	}
+
+	async fn call_will_appear(&self, instance: &Instance) -> Result<()> {
+		let Some(settings) = self.settings(instance).await? else {
+			return Ok(());
+		};
+		self.action.will_appear(instance, &settings).await
 	}
 
-	async fn call_will_disappear(&self, instance: &Instance, event: GenericInstancePayload) -> Result<()> {
-		let settings = deserialize_settings::<A>(event.settings);
-		self.0.will_disappear(instance, &settings).await
+	async fn call_will_disappear(&self, instance: &Instance) -> Result<()> {
+		let Some(settings) = self.settings(instance).await? else {
+			return Ok(());
+		};
+		self.action.will_disappear(instance, &settings).await
 	}
 
-	async fn call_key_down(&self, instance: &Instance, event: GenericInstancePayload) -> Result<()> {
-		let settings = deserialize_settings::<A>(event.settings);
-		self.0.key_down(instance, &settings).await
+	async fn call_key_down(&self, instance: &Instance) -> Result<()> {
+		let Some(settings) = self.settings(instance).await? else {
+			return Ok(());
+		};
+		K::key_down(&self.action, instance, &settings).await
 	}
 
-	async fn call_key_up(&self, instance: &Instance, event: GenericInstancePayload) -> Result<()> {
-		let settings = deserialize_settings::<A>(event.settings);
-		self.0.key_up(instance, &settings).await
+	async fn call_key_up(&self, instance: &Instance) -> Result<()> {
+		let Some(settings) = self.settings(instance).await? else {
+			return Ok(());
+		};
+		K::key_up(&self.action, instance, &settings).await
 	}
 
 	async fn call_dial_rotate(&self, instance: &Instance, event: DialRotatePayload) -> Result<()> {
-		let settings = deserialize_settings::<A>(event.settings);
-		self.0
-			.dial_rotate(instance, &settings, event.ticks, event.pressed)
-			.await
+		let Some(settings) = self.settings(instance).await? else {
+			return Ok(());
+		};
+		E::dial_rotate(&self.action, instance, &settings, event).await
 	}
 
-	async fn call_dial_down(&self, instance: &Instance, event: DialPressPayload) -> Result<()> {
-		let settings = deserialize_settings::<A>(event.settings);
-		self.0.dial_down(instance, &settings).await
+	async fn call_dial_down(&self, instance: &Instance) -> Result<()> {
+		let Some(settings) = self.settings(instance).await? else {
+			return Ok(());
+		};
+		E::dial_down(&self.action, instance, &settings).await
 	}
 
-	async fn call_dial_up(&self, instance: &Instance, event: DialPressPayload) -> Result<()> {
-		let settings = deserialize_settings::<A>(event.settings);
-		self.0.dial_up(instance, &settings).await
+	async fn call_dial_up(&self, instance: &Instance) -> Result<()> {
+		let Some(settings) = self.settings(instance).await? else {
+			return Ok(());
+		};
+		E::dial_up(&self.action, instance, &settings).await
 	}
 
 	async fn call_touch_tap(&self, instance: &Instance, event: TouchTapPayload) -> Result<()> {
-		let settings = deserialize_settings::<A>(event.settings);
-		self.0.touch_tap(instance, &settings, event.tapPos, event.hold).await
+		let Some(settings) = self.settings(instance).await? else {
+			return Ok(());
+		};
+		E::touch_tap(&self.action, instance, &settings, event).await
 	}
 
-	async fn call_did_receive_settings(&self, instance: &Instance, event: GenericInstancePayload) -> Result<()> {
-		let settings = deserialize_settings::<A>(event.settings);
-		self.0.did_receive_settings(instance, &settings).await
+	async fn call_did_receive_settings(&self, instance: &Instance) -> Result<()> {
+		let Some(settings) = self.settings(instance).await? else {
+			return Ok(());
+		};
+		self.action.did_receive_settings(instance, &settings).await
 	}
 
 	async fn call_title_parameters_did_change(
@@ -101,22 +283,70 @@ impl<A: Action> ErasedAction for ActionWrapper<A> {
 		instance: &Instance,
 		event: TitleParametersDidChangePayload,
 	) -> Result<()> {
-		let settings = deserialize_settings::<A>(event.settings.clone());
-		self.0.title_parameters_did_change(instance, &settings, &event).await
+		let Some(settings) = self.settings(instance).await? else {
+			return Ok(());
+		};
+		self.action
+			.title_parameters_did_change(instance, &settings, &event)
+			.await
 	}
 
 	async fn call_pi_did_appear(&self, instance: &Instance) -> Result<()> {
-		let settings = deserialize_settings::<A>(instance.settings_json.read().await.clone());
-		self.0.property_inspector_did_appear(instance, &settings).await
+		// Notices sent while the property inspector was closed were dropped, so send it again now that it is open
+		if let InvalidSettingsPolicy::NotifyPropertyInspector = self.action.invalid_settings_policy()
+			&& let Err(invalid) = instance.typed_settings::<A>().await
+		{
+			invalid.notified.store(false, Relaxed);
+			let raw = instance.settings_json.read().await.clone();
+			self.invalid_settings(instance, &raw, &invalid).await?;
+			return self
+				.action
+				.property_inspector_did_appear(instance, &A::Settings::default())
+				.await;
+		}
+		let Some(settings) = self.settings(instance).await? else {
+			return Ok(());
+		};
+		self.action.property_inspector_did_appear(instance, &settings).await
 	}
 
 	async fn call_pi_did_disappear(&self, instance: &Instance) -> Result<()> {
-		let settings = deserialize_settings::<A>(instance.settings_json.read().await.clone());
-		self.0.property_inspector_did_disappear(instance, &settings).await
+		let Some(settings) = self.settings(instance).await? else {
+			return Ok(());
+		};
+		self.action.property_inspector_did_disappear(instance, &settings).await
 	}
 
 	async fn call_send_to_plugin(&self, instance: &Instance, payload: serde_json::Value) -> Result<()> {
-		let settings = deserialize_settings::<A>(instance.settings_json.read().await.clone());
-		self.0.send_to_plugin(instance, &settings, &payload).await
+		let Some(settings) = self.settings(instance).await? else {
+			return Ok(());
+		};
+		self.action.send_to_plugin(instance, &settings, &payload).await
+	}
+
+	async fn call_settings_changed(&self, instance: &Instance, mut old: serde_json::Value) -> Result<()> {
+		migrate_settings::<A>(&mut old);
+		let old = match deserialize_settings::<A>(&old) {
+			Ok(old) => Arc::new(old),
+			// Invalid previous settings were reported to the property inspector while they were current
+			Err(error) => {
+				let invalid = InvalidSettings {
+					error,
+					notified: AtomicBool::new(true),
+				};
+				match self.invalid_settings(instance, &old, &invalid).await? {
+					Some(old) => old,
+					None => return Ok(()),
+				}
+			}
+		};
+		let Some(new) = self.settings(instance).await? else {
+			return Ok(());
+		};
+		self.action.settings_changed(instance, &old, &new).await
+	}
+
+	async fn call_on_error(&self, instance: &Instance, error: &OpenActionError) -> Result<()> {
+		self.action.on_error(instance, error).await
 	}
 }